@@ -0,0 +1,352 @@
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+use super::*;
+
+/// Error statistics for one channel over a range of samples.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ChannelQuality {
+    /// Signal-to-noise ratio in decibels.
+    ///
+    /// This is infinite if both buffers are identical.
+    pub snr: f64,
+
+    /// Peak signal-to-noise ratio in decibels, relative to a full scale 16-bit sample.
+    ///
+    /// This is infinite if both buffers are identical.
+    pub psnr: f64,
+
+    /// Root mean square of the error.
+    pub rms_error: f64,
+
+    /// Largest absolute difference between two samples.
+    pub max_error: u32,
+
+    /// Number of samples compared.
+    pub samples: usize
+}
+
+/// Error statistics for one ADPCM block.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlockQuality {
+    /// Index of the block in the stream.
+    pub block: usize,
+
+    /// Statistics for each channel of the block.
+    pub channels: Vec<ChannelQuality>
+}
+
+/// Result of comparing two PCM streams.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QualityReport {
+    /// Statistics for each channel over the whole stream.
+    pub channels: Vec<ChannelQuality>,
+
+    /// Statistics for each block.
+    pub blocks: Vec<BlockQuality>
+}
+
+impl QualityReport {
+    /// Get the lowest signal-to-noise ratio of all channels, in decibels.
+    ///
+    /// Returns `None` if there are no channels.
+    pub fn min_snr(&self) -> Option<f64> {
+        self.channels.iter().map(|c| c.snr).reduce(f64::min)
+    }
+
+    /// Get the block with the lowest signal-to-noise ratio on the given channel.
+    ///
+    /// Returns `None` if there are no blocks or the channel is out of range.
+    pub fn worst_block(&self, channel: usize) -> Option<&BlockQuality> {
+        self.blocks.iter()
+            .filter_map(|b| Some((b, b.channels.get(channel)?.snr)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(b, _)| b)
+    }
+
+    /// Get the block with the lowest signal-to-noise ratio on any channel.
+    ///
+    /// Returns `None` if there are no blocks.
+    pub fn worst_block_overall(&self) -> Option<&BlockQuality> {
+        let block_snr = |b: &BlockQuality| b.channels.iter().map(|c| c.snr).fold(f64::INFINITY, f64::min);
+        self.blocks.iter().min_by(|a, b| block_snr(a).total_cmp(&block_snr(b)))
+    }
+}
+
+/// Running sums used to calculate [`ChannelQuality`].
+#[derive(Copy, Clone, Default)]
+struct ErrorAccumulator {
+    signal_power: f64,
+    noise_power: f64,
+    max_error: u32,
+    samples: usize
+}
+
+impl ErrorAccumulator {
+    fn add(&mut self, original: i16, decoded: i16) {
+        let error = original.abs_diff(decoded) as u32;
+        self.signal_power += (original as f64).powi(2);
+        self.noise_power += (error as f64).powi(2);
+        self.max_error = self.max_error.max(error);
+        self.samples += 1;
+    }
+
+    fn quality(&self) -> ChannelQuality {
+        let samples = self.samples.max(1) as f64;
+        let mean_noise = self.noise_power / samples;
+        let peak = i16::MAX as f64;
+
+        ChannelQuality {
+            // Identical silence would be 0/0
+            snr: if self.noise_power == 0.0 { f64::INFINITY } else { 10.0 * (self.signal_power / self.noise_power).log10() },
+            psnr: 10.0 * (peak * peak / mean_noise).log10(),
+            rms_error: mean_noise.sqrt(),
+            max_error: self.max_error,
+            samples: self.samples
+        }
+    }
+}
+
+/// Compare decoded PCM samples against the original PCM samples.
+///
+/// Both inputs are given per channel, and each sample of `decoded` is compared with the sample at the same position in
+/// `original`. If the sample counts differ, the extra samples at the end of the longer input are ignored. Blocks are
+/// 64 samples long, the same as an Xbox ADPCM block. Use [`compare_pcm_with_format`] for other block sizes.
+///
+/// # Panics
+///
+/// Panics if the channel counts differ.
+///
+/// # Example
+///
+/// ```
+/// use xbadpcm::compare_pcm;
+///
+/// let silence = [0i16; 100];
+/// let report = compare_pcm(&[&silence], &[&silence]);
+/// assert_eq!(report.min_snr(), Some(f64::INFINITY));
+/// assert_eq!(report.channels[0].psnr, f64::INFINITY);
+/// ```
+pub fn compare_pcm<B: AsRef<[C]>, C: AsRef<[i16]>, D: AsRef<[F]>, F: AsRef<[i16]>>(original: B, decoded: D) -> QualityReport {
+    compare_pcm_with_format(original, decoded, ADPCMBlockFormat::XBOX)
+}
+
+/// Compare decoded PCM samples against the original PCM samples, with blocks as long as a block of the given format.
+///
+/// This is the same as [`compare_pcm`], except blocks are [`ADPCMBlockFormat::samples_per_block`] samples long.
+///
+/// # Panics
+///
+/// Panics if the channel counts differ.
+///
+/// # Example
+///
+/// ```
+/// use xbadpcm::{ADPCMBlockFormat, compare_pcm_with_format};
+///
+/// let silence = [0i16; 1000];
+/// let format = ADPCMBlockFormat::ima(256, 1).unwrap();
+/// let report = compare_pcm_with_format(&[&silence], &[&silence], format);
+/// assert_eq!(report.blocks.len(), 2);
+/// assert_eq!(report.blocks[0].channels[0].samples, 505);
+/// ```
+pub fn compare_pcm_with_format<B: AsRef<[C]>, C: AsRef<[i16]>, D: AsRef<[F]>, F: AsRef<[i16]>>(original: B, decoded: D, format: ADPCMBlockFormat) -> QualityReport {
+    let samples_per_block = format.samples_per_block();
+    let original = original.as_ref();
+    let decoded = decoded.as_ref();
+    assert_eq!(original.len(), decoded.len(), "channel count of decoded audio does not match the original");
+
+    let sample_count = original.iter().zip(decoded.iter()).map(|(o, d)| o.as_ref().len().min(d.as_ref().len())).min().unwrap_or(0);
+    let mut report = QualityReport::default();
+
+    for block in 0..sample_count.div_ceil(samples_per_block) {
+        report.blocks.push(BlockQuality { block, channels: Vec::with_capacity(original.len()) });
+    }
+
+    for (o, d) in original.iter().zip(decoded.iter()) {
+        let mut total = ErrorAccumulator::default();
+        let o = &o.as_ref()[..sample_count];
+        let d = &d.as_ref()[..sample_count];
+
+        for (block, (o, d)) in report.blocks.iter_mut().zip(o.chunks(samples_per_block).zip(d.chunks(samples_per_block))) {
+            let mut block_total = ErrorAccumulator::default();
+            for (&o, &d) in o.iter().zip(d.iter()) {
+                block_total.add(o, d);
+                total.add(o, d);
+            }
+            block.channels.push(block_total.quality());
+        }
+
+        report.channels.push(total.quality());
+    }
+
+    report
+}
+
+/// Decode Xbox ADPCM data and compare it against the original PCM samples.
+///
+/// The first sample of a stream is stored only in the header of the first block and is not output by
+/// [`XboxADPCMDecoder`], so decoded samples are compared against the original starting from its second sample.
+/// Any samples past the end of the shorter stream, such as the padding added by [`XboxADPCMEncoder::finish`], are
+/// ignored.
+///
+/// # Panics
+///
+/// Panics if `original` does not have between 1 and 8 channels.
+///
+/// # Example
+///
+/// ```
+/// use xbadpcm::{XboxADPCMEncoder, compare_adpcm};
+///
+/// let pcm: Vec<i16> = (0..4096).map(|i| ((i as f64 / 20.0).sin() * 8000.0) as i16).collect();
+/// let mut adpcm = Vec::new();
+///
+/// let mut encoder = XboxADPCMEncoder::new(1, 3, &mut adpcm);
+/// encoder.encode(&[&pcm]).unwrap();
+/// encoder.finish().unwrap();
+///
+/// let report = compare_adpcm(&[&pcm], &adpcm);
+/// assert!(report.min_snr().unwrap() > 20.0);
+/// ```
+pub fn compare_adpcm<B: AsRef<[C]>, C: AsRef<[i16]>>(original: B, adpcm: &[u8]) -> QualityReport {
+    compare_adpcm_with_format(original, adpcm, ADPCMBlockFormat::XBOX)
+}
+
+/// Decode ADPCM data with the given block format and compare it against the original PCM samples.
+///
+/// This is the same as [`compare_adpcm`], except the first original sample is only skipped if the format does not
+/// output the header sample, and each entry of [`QualityReport::blocks`] covers one block of the format.
+///
+/// # Panics
+///
+/// Panics if `original` does not have between 1 and 8 channels.
+pub fn compare_adpcm_with_format<B: AsRef<[C]>, C: AsRef<[i16]>>(original: B, adpcm: &[u8], format: ADPCMBlockFormat) -> QualityReport {
+    let original = original.as_ref();
+
+    let mut decoded = PlanarPCMSink(std::vec![Vec::new(); original.len()]);
+    let mut decoder = XboxADPCMDecoder::with_format(original.len(), format, &mut decoded);
+    decoder.decode(adpcm).unwrap();
+    decoder.finish().unwrap();
+
+    let skip = !format.outputs_header_sample() as usize;
+    let original: Vec<&[i16]> = original.iter().map(|c| c.as_ref().get(skip..).unwrap_or(&[])).collect();
+    compare_pcm_with_format(&original, &decoded.0, format)
+}
+
+/// Result of [`encode_and_verify`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifiedEncode {
+    /// Encoded ADPCM data.
+    pub adpcm: Vec<u8>,
+
+    /// Number of samples per channel encoded.
+    pub samples: usize,
+
+    /// Time spent encoding and verifying.
+    pub elapsed: Duration
+}
+
+impl VerifiedEncode {
+    /// Get the number of samples per channel encoded and verified per second.
+    ///
+    /// This is 0 if no samples were encoded, or infinite if samples were encoded but no time was measured.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use xbadpcm::VerifiedEncode;
+    ///
+    /// let result = VerifiedEncode { adpcm: Vec::new(), samples: 1000, elapsed: Duration::from_millis(500) };
+    /// assert_eq!(result.samples_per_second(), 2000.0);
+    ///
+    /// assert_eq!(VerifiedEncode::default().samples_per_second(), 0.0);
+    /// ```
+    pub fn samples_per_second(&self) -> f64 {
+        if self.samples == 0 {
+            return 0.0
+        }
+        self.samples as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Encode PCM samples with verification enabled, measuring how long encoding and verifying took.
+///
+/// Each block is decoded as it is written and compared with the encoder's reconstruction, as with
+/// [`XboxADPCMEncoder::set_verify`]. The first block that does not match is returned as an error.
+///
+/// # Panics
+///
+/// Panics if `input` does not have between 1 and 8 channels, or if its channels have different lengths.
+///
+/// # Example
+///
+/// ```
+/// use xbadpcm::{ADPCMBlockFormat, encode_and_verify};
+///
+/// let pcm: Vec<i16> = (0..4096).map(|i| ((i as f64 / 20.0).sin() * 8000.0) as i16).collect();
+///
+/// let result = encode_and_verify(&[&pcm, &pcm], 3, ADPCMBlockFormat::XBOX).unwrap();
+/// assert_eq!(result.samples, 4096);
+/// assert!(result.samples_per_second() > 0.0);
+/// ```
+pub fn encode_and_verify<B: AsRef<[C]>, C: AsRef<[i16]>>(input: B, lookahead: u8, format: ADPCMBlockFormat) -> Result<VerifiedEncode, VerifyError> {
+    let input = input.as_ref();
+    let samples = input.first().map_or(0, |c| c.as_ref().len());
+
+    let start = Instant::now();
+    let mut sink = VerifiedEncodeSink(Vec::new());
+    let mut encoder = XboxADPCMEncoder::with_format(input.len(), lookahead, format, &mut sink);
+    encoder.set_verify(true);
+    encoder.encode(input)?;
+    encoder.finish()?;
+    let elapsed = start.elapsed();
+
+    Ok(VerifiedEncode { adpcm: sink.0, samples, elapsed })
+}
+
+/// Encode sink that can return verification errors.
+struct VerifiedEncodeSink(Vec<u8>);
+
+impl XboxADPCMEncodeSink for VerifiedEncodeSink {
+    type Error = VerifyError;
+
+    fn reserve(&mut self, bytes_amount: usize) -> Result<(), Self::Error> {
+        self.0.reserve_exact(bytes_amount);
+        Ok(())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.0.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Decode sink for a channel count only known at runtime.
+struct PlanarPCMSink(Vec<Vec<i16>>);
+
+impl XboxADPCMDecodeSink for PlanarPCMSink {
+    type Error = ();
+
+    fn reserve(&mut self, samples_amount: usize) -> Result<(), Self::Error> {
+        for c in &mut self.0 {
+            c.reserve_exact(samples_amount);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, samples: &[[i16; SAMPLES_PER_ADPCM_BLOCK]]) -> Result<(), Self::Error> {
+        for (c, s) in self.0.iter_mut().zip(samples.iter()) {
+            c.extend_from_slice(s);
+        }
+        Ok(())
+    }
+
+    fn write_partial(&mut self, samples: &[[i16; SAMPLES_PER_ADPCM_BLOCK]], samples_amount: usize) -> Result<(), Self::Error> {
+        for (c, s) in self.0.iter_mut().zip(samples.iter()) {
+            c.extend_from_slice(&s[..samples_amount]);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use super::*;
+
+/// Header of one channel of an Xbox ADPCM block.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct XboxADPCMBlockHeader {
+    /// Uncompressed sample the channel starts with
+    pub sample: i16,
+
+    /// Index into the step table (0-88)
+    pub step_index: u8,
+
+    /// Unused byte, which is normally 0
+    pub reserved: u8
+}
+
+/// One channel of an Xbox ADPCM block.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct XboxADPCMBlockChannel {
+    /// Block header
+    pub header: XboxADPCMBlockHeader,
+
+    /// 4-bit samples in the order they are decoded, each stored in the lower four bits
+    pub nibbles: [u8; SAMPLES_PER_ADPCM_BLOCK]
+}
+
+impl Default for XboxADPCMBlockChannel {
+    fn default() -> Self {
+        XboxADPCMBlockChannel {
+            header: XboxADPCMBlockHeader::default(),
+            nibbles: [0u8; SAMPLES_PER_ADPCM_BLOCK]
+        }
+    }
+}
+
+/// One Xbox ADPCM block for all channels.
+///
+/// This can be used to inspect or modify blocks without decoding them.
+///
+/// # Example
+///
+/// ```
+/// use xbadpcm::XboxADPCMBlock;
+///
+/// let mut data = vec![0u8; XboxADPCMBlock::size(2)];
+/// data[0] = 0x34;
+/// data[1] = 0x12;
+/// data[2] = 10;
+///
+/// let mut block = XboxADPCMBlock::parse(&data, 2).unwrap();
+/// assert_eq!(block.channels()[0].header.sample, 0x1234);
+/// assert_eq!(block.channels()[0].header.step_index, 10);
+///
+/// block.channels_mut()[1].nibbles[0] = 0x7;
+///
+/// let mut output = [0u8; 72];
+/// block.serialize(&mut output);
+/// assert_eq!(output[12], 0x07);
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct XboxADPCMBlock {
+    /// Channels
+    channels: [XboxADPCMBlockChannel; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Number of channels
+    num_channels: usize
+}
+
+impl XboxADPCMBlock {
+    /// Size of one channel's part of a block in bytes.
+    pub const CHANNEL_SIZE: usize = ADPCM_BLOCK_SIZE;
+
+    /// Number of 4-bit samples per channel in a block, which is also the number of samples a block decodes to.
+    pub const SAMPLES_PER_BLOCK: usize = SAMPLES_PER_ADPCM_BLOCK;
+
+    /// Maximum number of channels in a block.
+    pub const MAX_CHANNELS: usize = MAX_AUDIO_CHANNEL_COUNT;
+
+    /// Initialize a block of silence with the given channel count.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_channels` is not between 1 and 8.
+    pub fn new(num_channels: usize) -> XboxADPCMBlock {
+        assert!(num_channels > 0 && num_channels <= MAX_AUDIO_CHANNEL_COUNT, "num_channels must be between 1 and {}", MAX_AUDIO_CHANNEL_COUNT);
+
+        XboxADPCMBlock {
+            channels: [XboxADPCMBlockChannel::default(); MAX_AUDIO_CHANNEL_COUNT],
+            num_channels
+        }
+    }
+
+    /// Parse a block with the given channel count from the start of `data`.
+    ///
+    /// Returns `None` if `data` is shorter than a block.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_channels` is not between 1 and 8.
+    pub fn parse(data: &[u8], num_channels: usize) -> Option<XboxADPCMBlock> {
+        let mut block = XboxADPCMBlock::new(num_channels);
+        let data = data.get(..XboxADPCMBlock::size(num_channels))?;
+
+        for (c, header) in block.channels.iter_mut().zip(data.chunks_exact(4)) {
+            c.header = XboxADPCMBlockHeader {
+                sample: i16::from_le_bytes([header[0], header[1]]),
+                step_index: header[2],
+                reserved: header[3]
+            };
+        }
+
+        for (w, word) in data[num_channels * 4..].chunks_exact(4).enumerate() {
+            let nibbles = &mut block.channels[w % num_channels].nibbles[w / num_channels * SAMPLES_PER_CHUNK..];
+            for (i, b) in word.iter().enumerate() {
+                nibbles[i * 2] = b & 0xF;
+                nibbles[i * 2 + 1] = b >> 4;
+            }
+        }
+
+        Some(block)
+    }
+
+    /// Write the block to the start of `output`, returning the number of bytes written.
+    ///
+    /// The upper four bits of each nibble are ignored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `output` is shorter than a block.
+    pub fn serialize(&self, output: &mut [u8]) -> usize {
+        let num_channels = self.num_channels;
+        let size = XboxADPCMBlock::size(num_channels);
+        let output = &mut output[..size];
+
+        for (c, header) in self.channels().iter().zip(output.chunks_exact_mut(4)) {
+            header[0..2].copy_from_slice(&c.header.sample.to_le_bytes());
+            header[2] = c.header.step_index;
+            header[3] = c.header.reserved;
+        }
+
+        for (w, word) in output[num_channels * 4..].chunks_exact_mut(4).enumerate() {
+            let nibbles = &self.channels[w % num_channels].nibbles[w / num_channels * SAMPLES_PER_CHUNK..];
+            for (i, b) in word.iter_mut().enumerate() {
+                *b = (nibbles[i * 2] & 0xF) | (nibbles[i * 2 + 1] << 4);
+            }
+        }
+
+        size
+    }
+
+    /// Write the block to the sink.
+    pub fn write<E>(&self, sink: &mut dyn XboxADPCMEncodeSink<Error = E>) -> Result<(), E> {
+        let mut output = [0u8; ADPCM_BLOCK_SIZE * MAX_AUDIO_CHANNEL_COUNT];
+        let size = self.serialize(&mut output);
+        sink.write(&output[..size])
+    }
+
+    /// Decode the block, returning the samples for each channel.
+    ///
+    /// Only the first [`XboxADPCMBlock::num_channels`] channels are used.
+    pub fn decode(&self) -> [[i16; SAMPLES_PER_ADPCM_BLOCK]; MAX_AUDIO_CHANNEL_COUNT] {
+        let mut data = [0u8; ADPCM_BLOCK_SIZE * MAX_AUDIO_CHANNEL_COUNT];
+        self.serialize(&mut data);
+
+        let mut samples = [[0i16; SAMPLES_PER_ADPCM_BLOCK]; MAX_AUDIO_CHANNEL_COUNT];
+        decode_block(self.num_channels, &data, &mut samples);
+        samples
+    }
+
+    /// Get the number of channels.
+    pub fn num_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    /// Get the channels.
+    pub fn channels(&self) -> &[XboxADPCMBlockChannel] {
+        &self.channels[..self.num_channels]
+    }
+
+    /// Get the channels mutably.
+    pub fn channels_mut(&mut self) -> &mut [XboxADPCMBlockChannel] {
+        &mut self.channels[..self.num_channels]
+    }
+
+    /// Get the size of a block with the given channel count in bytes.
+    pub fn size(num_channels: usize) -> usize {
+        ADPCM_BLOCK_SIZE * num_channels
+    }
+
+    /// Get the duration of a block at the given sample rate in seconds.
+    pub fn duration(sample_rate: u32) -> f64 {
+        SAMPLES_PER_ADPCM_BLOCK as f64 / sample_rate as f64
+    }
+}
@@ -0,0 +1,201 @@
+use super::*;
+
+/// Encoder for any of the ADPCM formats supported by this crate.
+///
+/// This is implemented for [`XboxADPCMEncoder`] and [`MSADPCMEncoder`], so code that writes containers or converts
+/// files can be written once for all of them.
+///
+/// # Example
+///
+/// ```
+/// use xbadpcm::{ADPCMEncoder, MSADPCMEncoder, XboxADPCMEncoder};
+///
+/// fn encode_all<A: ADPCMEncoder>(encoder: &mut A, input: &[&[i16]]) -> Result<usize, A::Error> {
+///     encoder.encode(input)?;
+///     let last_block_samples = encoder.finish()?;
+///     Ok(last_block_samples)
+/// }
+///
+/// let samples = [0i16; 100];
+///
+/// let mut xbox_output = Vec::new();
+/// encode_all(&mut XboxADPCMEncoder::new(1, 3, &mut xbox_output), &[&samples]).unwrap();
+///
+/// let mut ms_output = Vec::new();
+/// encode_all(&mut MSADPCMEncoder::new(1, 256, &mut ms_output), &[&samples]).unwrap();
+///
+/// assert_eq!(xbox_output.len(), 36 * 2);
+/// assert_eq!(ms_output.len(), 256);
+/// ```
+pub trait ADPCMEncoder {
+    type Error: Sized;
+
+    /// Get the largest number of channels the encoder supports.
+    fn max_channels(&self) -> usize {
+        MAX_AUDIO_CHANNEL_COUNT
+    }
+
+    /// Get the number of channels being encoded.
+    fn num_channels(&self) -> usize;
+
+    /// Get the size of a block for all channels in bytes.
+    fn block_size(&self) -> usize;
+
+    /// Get the number of samples per channel a block decodes to.
+    fn samples_per_block(&self) -> usize;
+
+    /// Encode the given samples, with one slice for each channel.
+    ///
+    /// This may keep some samples in a buffer until more samples are passed or [`ADPCMEncoder::finish`] is called.
+    fn encode(&mut self, input: &[&[i16]]) -> Result<(), Self::Error>;
+
+    /// Encode all remaining samples and reset the encoder.
+    ///
+    /// Returns the number of samples per channel in the last block that came from the input.
+    fn finish(&mut self) -> Result<usize, Self::Error>;
+
+    /// Reset the encoder without writing any more samples.
+    fn reset(&mut self);
+}
+
+/// Decoder for any of the ADPCM formats supported by this crate.
+///
+/// This is implemented for [`XboxADPCMDecoder`] and [`MSADPCMDecoder`].
+pub trait ADPCMDecoder {
+    type Error: Sized;
+
+    /// Get the largest number of channels the decoder supports.
+    fn max_channels(&self) -> usize {
+        MAX_AUDIO_CHANNEL_COUNT
+    }
+
+    /// Get the number of channels being decoded.
+    fn num_channels(&self) -> usize;
+
+    /// Get the size of a block for all channels in bytes.
+    fn block_size(&self) -> usize;
+
+    /// Get the number of samples per channel a block decodes to.
+    fn samples_per_block(&self) -> usize;
+
+    /// Decode the given bytes.
+    fn decode(&mut self, input: &[u8]) -> Result<(), Self::Error>;
+
+    /// Write any samples not written yet and reset the decoder.
+    fn finish(&mut self) -> Result<(), Self::Error>;
+
+    /// Reset the decoder without writing any more samples.
+    fn reset(&mut self);
+}
+
+impl<'a, E: Sized> ADPCMEncoder for XboxADPCMEncoder<'a, E> {
+    type Error = E;
+
+    fn num_channels(&self) -> usize {
+        XboxADPCMEncoder::num_channels(self)
+    }
+
+    fn block_size(&self) -> usize {
+        self.format().block_size(XboxADPCMEncoder::num_channels(self))
+    }
+
+    fn samples_per_block(&self) -> usize {
+        self.format().samples_per_block()
+    }
+
+    fn encode(&mut self, input: &[&[i16]]) -> Result<(), E> {
+        XboxADPCMEncoder::encode(self, input)
+    }
+
+    fn finish(&mut self) -> Result<usize, E> {
+        XboxADPCMEncoder::finish(self)
+    }
+
+    fn reset(&mut self) {
+        XboxADPCMEncoder::reset(self)
+    }
+}
+
+impl<'a, E: Sized> ADPCMDecoder for XboxADPCMDecoder<'a, E> {
+    type Error = E;
+
+    fn num_channels(&self) -> usize {
+        XboxADPCMDecoder::num_channels(self)
+    }
+
+    fn block_size(&self) -> usize {
+        self.format().block_size(XboxADPCMDecoder::num_channels(self))
+    }
+
+    fn samples_per_block(&self) -> usize {
+        self.format().samples_per_block()
+    }
+
+    fn decode(&mut self, input: &[u8]) -> Result<(), E> {
+        XboxADPCMDecoder::decode(self, input)
+    }
+
+    fn finish(&mut self) -> Result<(), E> {
+        XboxADPCMDecoder::finish(self)
+    }
+
+    fn reset(&mut self) {
+        XboxADPCMDecoder::reset(self)
+    }
+}
+
+impl<'a, E: Sized> ADPCMEncoder for MSADPCMEncoder<'a, E> {
+    type Error = E;
+
+    fn num_channels(&self) -> usize {
+        MSADPCMEncoder::num_channels(self)
+    }
+
+    fn block_size(&self) -> usize {
+        MSADPCMEncoder::block_size(self)
+    }
+
+    fn samples_per_block(&self) -> usize {
+        MSADPCMEncoder::samples_per_block(self)
+    }
+
+    fn encode(&mut self, input: &[&[i16]]) -> Result<(), E> {
+        MSADPCMEncoder::encode(self, input)
+    }
+
+    fn finish(&mut self) -> Result<usize, E> {
+        MSADPCMEncoder::finish(self)
+    }
+
+    fn reset(&mut self) {
+        MSADPCMEncoder::reset(self)
+    }
+}
+
+impl<'a, E: Sized> ADPCMDecoder for MSADPCMDecoder<'a, E> {
+    type Error = E;
+
+    fn num_channels(&self) -> usize {
+        MSADPCMDecoder::num_channels(self)
+    }
+
+    fn block_size(&self) -> usize {
+        MSADPCMDecoder::block_size(self)
+    }
+
+    fn samples_per_block(&self) -> usize {
+        MSADPCMDecoder::samples_per_block(self)
+    }
+
+    fn decode(&mut self, input: &[u8]) -> Result<(), E> {
+        MSADPCMDecoder::decode(self, input)
+    }
+
+    fn finish(&mut self) -> Result<(), E> {
+        MSADPCMDecoder::finish(self)
+    }
+
+    fn reset(&mut self) {
+        MSADPCMDecoder::reset(self)
+    }
+}
@@ -0,0 +1,165 @@
+use super::*;
+
+/// Floating point sample type that can be converted to a 16-bit sample.
+///
+/// This is implemented for [`f32`] and [`f64`].
+pub trait FloatSample: Copy {
+    /// Convert the sample to an [`f64`].
+    fn to_f64(self) -> f64;
+}
+
+impl FloatSample for f32 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl FloatSample for f64 {
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+/// Method used for converting floating point samples to 16-bit samples.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FloatConversionMode {
+    /// Discard the fractional part, rounding towards zero.
+    Truncate,
+
+    /// Round to the nearest integer, rounding halfway cases away from zero.
+    Round,
+
+    /// Add triangular probability density function (TPDF) dither with an amplitude of ±1 LSB, then round.
+    TriangularDither
+}
+
+/// Seed used by [`FloatConverter::new`].
+pub const DEFAULT_DITHER_SEED: u64 = 0x5851F42D4C957F2D;
+
+/// Converts floating point samples normalized to ±1.0 to 16-bit samples.
+///
+/// Samples are scaled by 32768 and clipped to the range of an [`i16`], so a sample of exactly 1.0 is clipped to
+/// 32767. The number of clipped samples is counted and can be read with [`FloatConverter::clipped_samples`].
+///
+/// Dither noise comes from a deterministic pseudorandom number generator, so converting the same input with the same
+/// seed always gives the same output. The encoder converts samples in frame order, so this also holds regardless of
+/// whether the input is interleaved or how it is split between calls.
+#[derive(Clone, Debug)]
+pub struct FloatConverter {
+    /// Conversion mode
+    mode: FloatConversionMode,
+
+    /// Random number generator state
+    rng_state: u64,
+
+    /// Number of samples clipped so far
+    clipped_samples: u64
+}
+
+impl FloatConverter {
+    /// Initialize a converter with the given mode and [`DEFAULT_DITHER_SEED`].
+    pub fn new(mode: FloatConversionMode) -> FloatConverter {
+        FloatConverter::with_seed(mode, DEFAULT_DITHER_SEED)
+    }
+
+    /// Initialize a converter with the given mode and a seed for the dither noise.
+    pub fn with_seed(mode: FloatConversionMode, seed: u64) -> FloatConverter {
+        FloatConverter {
+            mode,
+            rng_state: seed,
+            clipped_samples: 0
+        }
+    }
+
+    /// Get the conversion mode.
+    pub fn mode(&self) -> FloatConversionMode {
+        self.mode
+    }
+
+    /// Get the number of samples that were clipped since the converter was created.
+    pub fn clipped_samples(&self) -> u64 {
+        self.clipped_samples
+    }
+
+    /// Convert a single sample.
+    pub fn convert<F: FloatSample>(&mut self, sample: F) -> i16 {
+        let scaled = sample.to_f64() * 32768.0;
+
+        let value = match self.mode {
+            FloatConversionMode::Truncate => scaled as i32,
+            FloatConversionMode::Round => round(scaled),
+            FloatConversionMode::TriangularDither => {
+                let noise = self.next_random() - self.next_random();
+                round(scaled + noise)
+            }
+        };
+
+        if value > i16::MAX as i32 || value < i16::MIN as i32 {
+            self.clipped_samples += 1;
+        }
+
+        clamp_sample(value) as i16
+    }
+
+    /// Get a random value between 0.0 (inclusive) and 1.0 (exclusive) using SplitMix64.
+    fn next_random(&mut self) -> f64 {
+        self.rng_state = self.rng_state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.rng_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Round to the nearest integer, rounding halfway cases away from zero.
+fn round(value: f64) -> i32 {
+    if value < 0.0 {
+        (value - 0.5) as i32
+    }
+    else {
+        (value + 0.5) as i32
+    }
+}
+
+/// Sample type that decoded 16-bit samples can be converted to.
+///
+/// This is implemented for the following types:
+/// - [`i16`], which is left as-is
+/// - [`i32`], which is scaled to the full 32-bit range
+/// - [`u8`], which is unsigned 8-bit with silence at 128, as used by 8-bit WAV files
+/// - [`f32`] and [`f64`], which are normalized to ±1.0
+pub trait OutputSample: Copy {
+    /// Convert a 16-bit sample.
+    fn from_i16(sample: i16) -> Self;
+}
+
+impl OutputSample for i16 {
+    fn from_i16(sample: i16) -> Self {
+        sample
+    }
+}
+
+impl OutputSample for i32 {
+    fn from_i16(sample: i16) -> Self {
+        (sample as i32) << 16
+    }
+}
+
+impl OutputSample for u8 {
+    fn from_i16(sample: i16) -> Self {
+        ((sample >> 8) + 128) as u8
+    }
+}
+
+impl OutputSample for f32 {
+    fn from_i16(sample: i16) -> Self {
+        sample as f32 / 32768.0
+    }
+}
+
+impl OutputSample for f64 {
+    fn from_i16(sample: i16) -> Self {
+        sample as f64 / 32768.0
+    }
+}
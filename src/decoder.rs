@@ -2,8 +2,8 @@ use core::convert::TryInto;
 
 use super::*;
 
-/// Maximum block size for all channels assuming max channels.
-pub(crate) const ADPCM_BUFFER_SIZE: usize = ADPCM_BLOCK_SIZE * MAX_AUDIO_CHANNEL_COUNT;
+/// Size of one word for all channels assuming max channels.
+const ADPCM_BUFFER_SIZE: usize = 4 * MAX_AUDIO_CHANNEL_COUNT;
 
 /// Writer for outputting PCM samples.
 ///
@@ -23,6 +23,99 @@ pub trait XboxADPCMDecodeSink {
     ///
     /// Implementing this is **required**.
     fn write(&mut self, samples: &[[i16; SAMPLES_PER_ADPCM_BLOCK]]) -> Result<(), Self::Error>;
+
+    /// Write the first `samples_amount` samples to the end of the output for each channel.
+    ///
+    /// This is used by [`XboxADPCMDecoder::finish`] for the samples left over at the end of the stream. Implementing
+    /// this is optional, but the default implementation writes all samples, with the unused ones set to silence.
+    fn write_partial(&mut self, samples: &[[i16; SAMPLES_PER_ADPCM_BLOCK]], samples_amount: usize) -> Result<(), Self::Error> {
+        self.write(samples)
+    }
+}
+
+/// Writer for outputting PCM samples converted to another sample format.
+///
+/// This is used with [`ConvertingDecodeSink`], and it is automatically implemented for [`Vec<T>`](std::vec::Vec) arrays
+/// between 1 and 8 if the `"std"` feature is enabled (which it is by default).
+#[allow(unused_variables)]
+pub trait XboxADPCMDecodeFormatSink<T: OutputSample> {
+    type Error: Sized;
+
+    /// Reserve an amount of samples for all channels.
+    ///
+    /// Implementing this is optional, but it can be used to hint the amount of samples to be written for allocations such as for memory-based buffers.
+    fn reserve(&mut self, samples_amount: usize) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Write the samples to the end of the output for each channel.
+    ///
+    /// Implementing this is **required**.
+    fn write(&mut self, samples: &[[T; SAMPLES_PER_ADPCM_BLOCK]]) -> Result<(), Self::Error>;
+
+    /// Write the first `samples_amount` samples to the end of the output for each channel.
+    ///
+    /// Implementing this is optional, but the default implementation writes all samples, with the unused ones set to
+    /// silence.
+    fn write_partial(&mut self, samples: &[[T; SAMPLES_PER_ADPCM_BLOCK]], samples_amount: usize) -> Result<(), Self::Error> {
+        self.write(samples)
+    }
+}
+
+/// Decode sink adapter that converts samples to another format before passing them to a [`XboxADPCMDecodeFormatSink`].
+///
+/// # Example
+///
+/// ```
+/// use xbadpcm::{XboxADPCMDecoder, ConvertingDecodeSink};
+///
+/// let mut output: [Vec<f32>; 2] = Default::default();
+/// let mut sink = ConvertingDecodeSink::new(&mut output);
+///
+/// let mut decoder = XboxADPCMDecoder::new(2, &mut sink);
+/// decoder.decode(&[0u8; 72]).unwrap();
+///
+/// assert_eq!(output[0].len(), 64);
+/// ```
+pub struct ConvertingDecodeSink<'a, T: OutputSample, E> {
+    /// Sink
+    sink: &'a mut dyn XboxADPCMDecodeFormatSink<T, Error = E>
+}
+
+impl<'a, T: OutputSample, E: Sized> ConvertingDecodeSink<'a, T, E> {
+    /// Initialize an adapter for the given sink.
+    pub fn new(sink: &'a mut dyn XboxADPCMDecodeFormatSink<T, Error = E>) -> ConvertingDecodeSink<'a, T, E> {
+        ConvertingDecodeSink { sink }
+    }
+}
+
+impl<'a, T: OutputSample, E: Sized> XboxADPCMDecodeSink for ConvertingDecodeSink<'a, T, E> {
+    type Error = E;
+
+    fn reserve(&mut self, samples_amount: usize) -> Result<(), Self::Error> {
+        self.sink.reserve(samples_amount)
+    }
+
+    fn write(&mut self, samples: &[[i16; SAMPLES_PER_ADPCM_BLOCK]]) -> Result<(), Self::Error> {
+        let converted = convert_samples::<T>(samples);
+        self.sink.write(&converted[..samples.len().min(MAX_AUDIO_CHANNEL_COUNT)])
+    }
+
+    fn write_partial(&mut self, samples: &[[i16; SAMPLES_PER_ADPCM_BLOCK]], samples_amount: usize) -> Result<(), Self::Error> {
+        let converted = convert_samples::<T>(samples);
+        self.sink.write_partial(&converted[..samples.len().min(MAX_AUDIO_CHANNEL_COUNT)], samples_amount)
+    }
+}
+
+/// Convert a batch of samples for each channel.
+fn convert_samples<T: OutputSample>(samples: &[[i16; SAMPLES_PER_ADPCM_BLOCK]]) -> [[T; SAMPLES_PER_ADPCM_BLOCK]; MAX_AUDIO_CHANNEL_COUNT] {
+    let mut converted = [[T::from_i16(0); SAMPLES_PER_ADPCM_BLOCK]; MAX_AUDIO_CHANNEL_COUNT];
+    for (c, s) in converted.iter_mut().zip(samples.iter()) {
+        for (c, &s) in c.iter_mut().zip(s.iter()) {
+            *c = T::from_i16(s);
+        }
+    }
+    converted
 }
 
 macro_rules! define_output_audio_sink {
@@ -44,6 +137,39 @@ macro_rules! define_output_audio_sink {
                 }
                 Ok(())
             }
+
+            fn write_partial(&mut self, samples: &[[i16; SAMPLES_PER_ADPCM_BLOCK]], samples_amount: usize) -> Result<(), Self::Error> {
+                for i in 0..$channel_count {
+                    self[i].extend_from_slice(&samples[i][..samples_amount]);
+                }
+                Ok(())
+            }
+        }
+
+        #[cfg(feature = "std")]
+        impl<T: OutputSample> XboxADPCMDecodeFormatSink<T> for [std::vec::Vec<T>; $channel_count] {
+            type Error = ();
+
+            fn reserve(&mut self, samples_amount: usize) -> Result<(), Self::Error> {
+                for i in 0..$channel_count {
+                    self[i].reserve_exact(samples_amount);
+                }
+                Ok(())
+            }
+
+            fn write(&mut self, samples: &[[T; SAMPLES_PER_ADPCM_BLOCK]]) -> Result<(), Self::Error> {
+                for i in 0..$channel_count {
+                    self[i].extend_from_slice(&samples[i]);
+                }
+                Ok(())
+            }
+
+            fn write_partial(&mut self, samples: &[[T; SAMPLES_PER_ADPCM_BLOCK]], samples_amount: usize) -> Result<(), Self::Error> {
+                for i in 0..$channel_count {
+                    self[i].extend_from_slice(&samples[i][..samples_amount]);
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -58,111 +184,544 @@ define_output_audio_sink!(6);
 define_output_audio_sink!(7);
 define_output_audio_sink!(8);
 
+/// Arithmetic followed by [`XboxADPCMDecoder`].
+///
+/// IMA ADPCM implementations differ in small ways, such as how the difference for each 4-bit sample is rounded, so
+/// decoders can disagree by a few LSBs. Use [`ADPCMConformance::IMAReference`] when output must match other tools
+/// exactly.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum ADPCMConformance {
+    /// Follow the decoder's own options, such as [`XboxADPCMDecoder::set_delta_mode`]. With the default options, this
+    /// is the same as [`ADPCMConformance::IMAReference`].
+    #[default]
+    Native,
+
+    /// Follow the arithmetic of the Intel/DVI IMA ADPCM reference decoder exactly, regardless of any other decoder
+    /// options.
+    ///
+    /// This is what Microsoft IMA ADPCM (`WAVE_FORMAT_IMA_ADPCM`) decoders and Python's `audioop` module do. Each
+    /// 4-bit sample adds `step >> 3` plus `step`, `step >> 1` and `step >> 2` for each of its set magnitude bits, the
+    /// sample is clamped to 16 bits, and then the step index is updated and clamped to 0-88. The lower four bits of
+    /// each byte are decoded first. The test vectors in `tests/vectors` are checked against this.
+    IMAReference
+}
+
 /// Xbox ADPCM decoder implementation.
+///
+/// This also decodes standard IMA ADPCM when initialized with [`XboxADPCMDecoder::with_format`].
 pub struct XboxADPCMDecoder<'a, E> {
     /// Number of channels
     num_channels: usize,
 
-    /// Buffer to write
+    /// Block format
+    format: ADPCMBlockFormat,
+
+    /// Arithmetic to follow
+    conformance: ADPCMConformance,
+
+    /// How sample deltas are calculated, unless overridden by `conformance`
+    delta_mode: ADPCMDeltaMode,
+
+    /// Block layout
+    layout: ADPCMBlockLayout,
+
+    /// Bytes of an incomplete group of mono blocks, if the layout is not interleaved
+    layout_buffer: LayoutBuffer,
+
+    /// Buffer containing one word for each channel
     buffer: [u8; ADPCM_BUFFER_SIZE],
 
     /// Number of bytes used
     buffer_size: usize,
 
+    /// Number of words per channel decoded in the current block, counting the header
+    block_words_decoded: usize,
+
+    /// Decoder state for each channel
+    channels: [ADPCMChannel; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Decoded samples not yet written
+    samples: [[i16; SAMPLES_PER_ADPCM_BLOCK]; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Number of samples per channel in `samples`
+    samples_size: usize,
+
+    /// Number of bytes passed to the decoder since the last reset
+    bytes_decoded: usize,
+
     /// Sink
     sink: &'a mut dyn XboxADPCMDecodeSink<Error = E>
 }
 
+/// Saved state of an [`XboxADPCMDecoder`], including its configuration, the bytes of an incomplete word or group of
+/// blocks, the position in the current block, and decoded samples that were not written to the sink yet.
+///
+/// This can be serialized with serde if the `"serde"` feature is enabled, so decoding can be resumed in another process
+/// with [`XboxADPCMDecoder::from_state`].
+///
+/// # Example
+///
+/// ```
+/// use xbadpcm::XboxADPCMDecoder;
+///
+/// let adpcm_data = vec![0x11u8; 36 * 10];
+///
+/// let mut output = [Vec::new()];
+/// let mut decoder = XboxADPCMDecoder::new(1, &mut output);
+/// decoder.decode(&adpcm_data[..150]).unwrap();
+/// let state = decoder.state();
+/// assert_eq!(state.bytes_decoded(), 150);
+///
+/// let mut resumed_output = [Vec::new()];
+/// let mut decoder = XboxADPCMDecoder::from_state(&state, &mut resumed_output).unwrap();
+/// decoder.decode(&adpcm_data[150..]).unwrap();
+///
+/// let mut uninterrupted_output = [Vec::new()];
+/// XboxADPCMDecoder::new(1, &mut uninterrupted_output).decode(&adpcm_data).unwrap();
+///
+/// assert_eq!([&output[0][..], &resumed_output[0][..]].concat(), uninterrupted_output[0]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct XboxADPCMDecoderState {
+    num_channels: usize,
+    format: ADPCMBlockFormat,
+    conformance: ADPCMConformance,
+    delta_mode: ADPCMDeltaMode,
+    layout: ADPCMBlockLayout,
+    layout_buffer: LayoutBuffer,
+    buffer: [u8; ADPCM_BUFFER_SIZE],
+    buffer_size: usize,
+    block_words_decoded: usize,
+    channels: [ADPCMChannel; MAX_AUDIO_CHANNEL_COUNT],
+    #[cfg_attr(feature = "serde", serde(with = "serde_arrays::nested"))]
+    samples: [[i16; SAMPLES_PER_ADPCM_BLOCK]; MAX_AUDIO_CHANNEL_COUNT],
+    samples_size: usize,
+    bytes_decoded: usize
+}
+
+impl XboxADPCMDecoderState {
+    /// Get the number of bytes passed to the decoder since it was last reset, which is where to resume reading the
+    /// input.
+    pub fn bytes_decoded(&self) -> usize {
+        self.bytes_decoded
+    }
+
+    /// Check that the state could have come from a decoder, since it may have been deserialized from anywhere.
+    fn is_valid(&self) -> bool {
+        let num_channels_valid = self.num_channels > 0 && self.num_channels <= MAX_AUDIO_CHANNEL_COUNT;
+        num_channels_valid
+            && self.format.is_valid()
+            && self.buffer_size < 4 * self.num_channels
+            && self.block_words_decoded < self.format.channel_block_size() / 4
+            && self.samples_size < SAMPLES_PER_ADPCM_BLOCK
+            && self.channels.iter().all(ADPCMChannel::is_valid)
+            && self.layout_valid()
+    }
+
+    /// Check that the layout is supported and the bytes held back for it are less than one group.
+    ///
+    /// Words are only buffered for the interleaved layout, and other layouts hold back bytes until a group is complete.
+    #[cfg(feature = "std")]
+    fn layout_valid(&self) -> bool {
+        match self.layout {
+            ADPCMBlockLayout::Interleaved => self.layout_buffer.is_empty(),
+            ADPCMBlockLayout::Planar => self.buffer_size == 0,
+            ADPCMBlockLayout::Stride(n) => {
+                n > 0 && self.buffer_size == 0 && self.layout_buffer.len() < n.saturating_mul(self.format.block_size(self.num_channels))
+            }
+        }
+    }
+
+    /// Check that the layout is supported, which without std is only the interleaved layout.
+    #[cfg(not(feature = "std"))]
+    fn layout_valid(&self) -> bool {
+        self.layout == ADPCMBlockLayout::Interleaved
+    }
+}
+
 impl<'a, E: Sized> XboxADPCMDecoder<'a, E> {
     /// Initialize an Xbox ADPCM decoder with the given channel count and the output.
     pub fn new(num_channels: usize, sink: &'a mut dyn XboxADPCMDecodeSink<Error = E>) -> XboxADPCMDecoder<'a, E> {
+        XboxADPCMDecoder::with_format(num_channels, ADPCMBlockFormat::XBOX, sink)
+    }
+
+    /// Initialize a decoder with the given channel count, block format and the output.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_channels` is not between 1 and 8.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::{ADPCMBlockFormat, XboxADPCMDecoder};
+    ///
+    /// let format = ADPCMBlockFormat::ima(512, 1).unwrap();
+    /// let mut output = [Vec::new()];
+    ///
+    /// let mut decoder = XboxADPCMDecoder::with_format(1, format, &mut output);
+    /// decoder.decode(&[0u8; 1024]).unwrap();
+    /// decoder.finish().unwrap();
+    ///
+    /// assert_eq!(output[0].len(), 1017 * 2);
+    /// ```
+    pub fn with_format(num_channels: usize, format: ADPCMBlockFormat, sink: &'a mut dyn XboxADPCMDecodeSink<Error = E>) -> XboxADPCMDecoder<'a, E> {
         assert!(num_channels > 0 && num_channels <= MAX_AUDIO_CHANNEL_COUNT, "num_channels must be between 1 and {}", MAX_AUDIO_CHANNEL_COUNT);
 
         XboxADPCMDecoder {
             num_channels,
-            buffer: [0u8; ADPCM_BLOCK_SIZE * MAX_AUDIO_CHANNEL_COUNT],
+            format,
+            conformance: ADPCMConformance::Native,
+            delta_mode: ADPCMDeltaMode::ShiftAdd,
+            layout: ADPCMBlockLayout::Interleaved,
+            layout_buffer: LayoutBuffer::new(),
+            buffer: [0u8; ADPCM_BUFFER_SIZE],
             buffer_size: 0,
+            block_words_decoded: 0,
+            channels: <[ADPCMChannel; MAX_AUDIO_CHANNEL_COUNT]>::default(),
+            samples: [[0i16; SAMPLES_PER_ADPCM_BLOCK]; MAX_AUDIO_CHANNEL_COUNT],
+            samples_size: 0,
+            bytes_decoded: 0,
+            sink
+        }
+    }
+
+    /// Initialize a decoder from a saved state, writing to the given output.
+    ///
+    /// Returns `None` if the state is invalid, which can only happen if it was deserialized from invalid data.
+    pub fn from_state(state: &XboxADPCMDecoderState, sink: &'a mut dyn XboxADPCMDecodeSink<Error = E>) -> Option<XboxADPCMDecoder<'a, E>> {
+        if !state.is_valid() {
+            return None
+        }
+
+        Some(XboxADPCMDecoder {
+            num_channels: state.num_channels,
+            format: state.format,
+            conformance: state.conformance,
+            delta_mode: state.delta_mode,
+            layout: state.layout,
+            layout_buffer: state.layout_buffer.clone(),
+            buffer: state.buffer,
+            buffer_size: state.buffer_size,
+            block_words_decoded: state.block_words_decoded,
+            channels: state.channels,
+            samples: state.samples,
+            samples_size: state.samples_size,
+            bytes_decoded: state.bytes_decoded,
             sink
+        })
+    }
+
+    /// Save the state of the decoder.
+    ///
+    /// Samples that were decoded but not written to the sink yet are saved, too, so a decoder restored with
+    /// [`XboxADPCMDecoder::from_state`] continues the output exactly where this one stops.
+    pub fn state(&self) -> XboxADPCMDecoderState {
+        XboxADPCMDecoderState {
+            num_channels: self.num_channels,
+            format: self.format,
+            conformance: self.conformance,
+            delta_mode: self.delta_mode,
+            layout: self.layout,
+            layout_buffer: self.layout_buffer.clone(),
+            buffer: self.buffer,
+            buffer_size: self.buffer_size,
+            block_words_decoded: self.block_words_decoded,
+            channels: self.channels,
+            samples: self.samples,
+            samples_size: self.samples_size,
+            bytes_decoded: self.bytes_decoded
         }
     }
 
-    /// Decode the given byte array of Xbox ADPCM blocks.
+    /// Get the number of bytes passed to the decoder since it was last reset.
+    pub fn bytes_decoded(&self) -> usize {
+        self.bytes_decoded
+    }
+
+    /// Get the block format.
+    pub fn format(&self) -> ADPCMBlockFormat {
+        self.format
+    }
+
+    /// Get the number of channels.
+    pub fn num_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    /// Get the arithmetic the decoder follows.
+    pub fn conformance(&self) -> ADPCMConformance {
+        self.conformance
+    }
+
+    /// Set the arithmetic the decoder follows.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::{ADPCMConformance, XboxADPCMDecoder};
+    ///
+    /// let mut output = [Vec::new()];
+    /// let mut decoder = XboxADPCMDecoder::new(1, &mut output);
+    /// decoder.set_conformance(ADPCMConformance::IMAReference);
+    /// decoder.decode(&[0u8; 36]).unwrap();
+    /// ```
+    pub fn set_conformance(&mut self, conformance: ADPCMConformance) {
+        self.conformance = conformance;
+    }
+
+    /// Get how sample deltas are calculated.
+    pub fn delta_mode(&self) -> ADPCMDeltaMode {
+        self.delta_mode
+    }
+
+    /// Set how sample deltas are calculated.
+    ///
+    /// This is ignored when following [`ADPCMConformance::IMAReference`].
+    pub fn set_delta_mode(&mut self, delta_mode: ADPCMDeltaMode) {
+        self.delta_mode = delta_mode;
+    }
+
+    /// Get the block layout.
+    #[cfg(feature = "std")]
+    pub fn layout(&self) -> ADPCMBlockLayout {
+        self.layout
+    }
+
+    /// Set the block layout.
+    ///
+    /// For layouts other than [`ADPCMBlockLayout::Interleaved`], blocks are held back until a whole group of blocks was
+    /// passed to [`XboxADPCMDecoder::decode`]. The last group is decoded by [`XboxADPCMDecoder::finish`], which for
+    /// [`ADPCMBlockLayout::Planar`] is the whole stream, and bytes of an incomplete block at its end are discarded.
+    /// This must not be changed while decoding.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the layout is [`ADPCMBlockLayout::Stride`] with a stride of 0.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::{ADPCMBlockLayout, XboxADPCMDecoder};
+    ///
+    /// // Two blocks of the left channel followed by two blocks of the right channel
+    /// let mut adpcm = vec![0u8; 36 * 4];
+    /// adpcm[36 * 2] = 100;
+    ///
+    /// let mut output = [Vec::new(), Vec::new()];
+    /// let mut decoder = XboxADPCMDecoder::new(2, &mut output);
+    /// decoder.set_layout(ADPCMBlockLayout::Planar);
+    /// decoder.decode(&adpcm[..50]).unwrap();
+    /// decoder.decode(&adpcm[50..]).unwrap();
+    /// decoder.finish().unwrap();
+    ///
+    /// assert_eq!(output[0].len(), 128);
+    /// assert_eq!(output[1][0], 100);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn set_layout(&mut self, layout: ADPCMBlockLayout) {
+        assert_ne!(layout, ADPCMBlockLayout::Stride(0), "stride must not be 0");
+        self.layout = layout;
+    }
+
+    /// Decode the given byte array of ADPCM blocks.
+    ///
+    /// Samples are written to the sink 64 at a time. If the block format does not decode to a multiple of 64 samples,
+    /// call [`XboxADPCMDecoder::finish`] after the last call to write the rest.
     pub fn decode(&mut self, input: &[u8]) -> Result<(), E> {
         let input_len = input.len();
-        let max_buffer_size = ADPCM_BLOCK_SIZE * self.num_channels;
+        let word_size = 4 * self.num_channels;
+        let block_size = self.format.block_size(self.num_channels);
 
         // Calculate how many samples we will process.
-        let total_bytes_after_this = input_len + self.buffer_size;
+        let total_bytes_after_this = input_len + self.buffer_size + self.block_words_decoded * word_size;
+
+        // Calculate how many samples to reserve, even if we may not include everything
+        let samples_to_reserve = self.format.decoded_samples(total_bytes_after_this.next_multiple_of(block_size), self.num_channels);
+        if samples_to_reserve > 0 {
+            self.sink.reserve(samples_to_reserve)?;
+        }
 
-        // Calculate how many bytes to reserve, even if we may not include everything
-        let blocks_to_reserve = (total_bytes_after_this + (max_buffer_size - 1)) / max_buffer_size;
-        if blocks_to_reserve > 0 {
-            self.sink.reserve(blocks_to_reserve * SAMPLES_PER_ADPCM_BLOCK)?;
+        self.bytes_decoded += input_len;
+
+        #[cfg(feature = "std")]
+        if self.layout != ADPCMBlockLayout::Interleaved {
+            self.layout_buffer.extend_from_slice(input);
+            return self.decode_layout_groups(false)
         }
 
         // Load the bytes
         let mut bytes_loaded = 0;
         while bytes_loaded != input_len {
-            let bytes_free = max_buffer_size - self.buffer_size;
-            let bytes_that_can_be_loaded = bytes_free.min(input_len);
-            for b in 0..bytes_that_can_be_loaded {
-                self.buffer[self.buffer_size + b] = input[bytes_loaded + b];
-            }
+            let bytes_free = word_size - self.buffer_size;
+            let bytes_that_can_be_loaded = bytes_free.min(input_len - bytes_loaded);
+            self.buffer[self.buffer_size..self.buffer_size + bytes_that_can_be_loaded].copy_from_slice(&input[bytes_loaded..bytes_loaded + bytes_that_can_be_loaded]);
             self.buffer_size += bytes_that_can_be_loaded;
             bytes_loaded += bytes_that_can_be_loaded;
-            if self.buffer_size == max_buffer_size {
-                self.decode_block()?;
+            if self.buffer_size == word_size {
+                self.decode_words()?;
             }
         }
 
         Ok(())
     }
 
-    /// Decode bytes from the buffer.
-    fn decode_block(&mut self) -> Result<(), E> {
-        let mut samples_to_output = [[0i16; SAMPLES_PER_ADPCM_BLOCK]; MAX_AUDIO_CHANNEL_COUNT];
+    /// Write any decoded samples that were not written yet and then reset the decoder.
+    ///
+    /// Bytes of an incomplete word at the end of the input are discarded.
+    pub fn finish(&mut self) -> Result<(), E> {
+        #[cfg(feature = "std")]
+        self.decode_layout_groups(true)?;
 
-        let mut last_samples = [0i16; MAX_AUDIO_CHANNEL_COUNT];
-        let mut last_step_index = [0usize; MAX_AUDIO_CHANNEL_COUNT];
+        let samples_size = self.samples_size;
+        self.reset();
 
-        // Initialize with the header
-        let mut input_offset = 0;
-        for ch in 0..self.num_channels {
-            let header = &self.buffer[input_offset..input_offset+4];
+        if samples_size != 0 {
+            for c in &mut self.samples {
+                c[samples_size..].fill(0);
+            }
+            self.sink.write_partial(&self.samples, samples_size)?;
+        }
 
-            let low = header[0] as u16;
-            let high = header[1] as u16;
-            let sample = ((low) | (high << 8)) as i16;
+        Ok(())
+    }
 
-            last_samples[ch] = sample;
-            last_step_index[ch] = clamp_table_index(header[2] as isize);
+    /// Reset the decoder immediately without writing any more samples.
+    pub fn reset(&mut self) {
+        self.buffer_size = 0;
+        self.block_words_decoded = 0;
+        self.samples_size = 0;
+        self.bytes_decoded = 0;
+        #[cfg(feature = "std")]
+        self.layout_buffer.clear();
+    }
+
+    /// Decode whole groups of mono blocks in the layout buffer, one word of each channel at a time, including a shorter
+    /// group at the end if finishing.
+    #[cfg(feature = "std")]
+    fn decode_layout_groups(&mut self, finishing: bool) -> Result<(), E> {
+        let channel_block_size = self.format.channel_block_size();
+        let block_size = self.format.block_size(self.num_channels);
+        let group_size = self.layout.group_size(&self.format, self.num_channels);
 
-            input_offset += 4;
+        let mut bytes_used = 0;
+        loop {
+            let bytes_left = self.layout_buffer.len() - bytes_used;
+            let group_len = match group_size {
+                Some(g) if bytes_left >= g => g,
+                _ if finishing && bytes_left >= block_size => bytes_left,
+                _ => break
+            };
+
+            // Each channel has the same number of blocks in a group, even in a shorter group at the end.
+            let blocks = group_len / block_size;
+            for block in 0..blocks {
+                for word in 0..channel_block_size / 4 {
+                    for c in 0..self.num_channels {
+                        let offset = bytes_used + (c * blocks + block) * channel_block_size + word * 4;
+                        self.buffer[c * 4..c * 4 + 4].copy_from_slice(&self.layout_buffer[offset..offset + 4]);
+                    }
+                    self.decode_words()?;
+                }
+            }
+            bytes_used += group_len;
         }
 
-        // Decode it
-        for c in 0..CHUNKS_PER_BLOCK {
-            let output_offset = c * CHUNKS_PER_BLOCK;
-            for ch in 0..self.num_channels {
+        self.layout_buffer.drain(..bytes_used);
+        Ok(())
+    }
 
-                let mut data = u32::from_le_bytes(self.buffer[input_offset..input_offset+4].try_into().unwrap());
-                for s in 0..SAMPLES_PER_CHUNK {
-                    let nibble = (data & 0xF) as u8;
-                    let new_sample = clamp_sample(last_samples[ch] as i32 + calculate_delta(STEP_TABLE[last_step_index[ch]], nibble)) as i16;
-                    last_step_index[ch] = clamp_table_index((last_step_index[ch] as isize) + INDEX_TABLE[nibble as usize]);
-                    last_samples[ch] = new_sample;
-                    samples_to_output[ch][output_offset + s] = new_sample;
+    /// Decode one word for each channel from the buffer.
+    fn decode_words(&mut self) -> Result<(), E> {
+        let mut decoded = [[0i16; SAMPLES_PER_CHUNK]; MAX_AUDIO_CHANNEL_COUNT];
+        let decoded_count;
 
-                    data >>= 4; // right shift to get the next four bits
+        if self.block_words_decoded == 0 {
+            for (ch, header) in self.channels[..self.num_channels].iter_mut().zip(self.buffer.chunks_exact(4)) {
+                *ch = read_header(header);
+            }
+            for (d, ch) in decoded.iter_mut().zip(self.channels.iter()) {
+                d[0] = ch.pcmdata as i16;
+            }
+            decoded_count = self.format.outputs_header_sample() as usize;
+        }
+        else {
+            for ((ch, word), d) in self.channels[..self.num_channels].iter_mut().zip(self.buffer.chunks_exact(4)).zip(decoded.iter_mut()) {
+                let word = u32::from_le_bytes(word.try_into().unwrap());
+                match self.conformance {
+                    ADPCMConformance::Native => decode_word(ch, word, d, self.delta_mode),
+                    ADPCMConformance::IMAReference => decode_word_ima_reference(ch, word, d)
                 }
-
-                input_offset += 4;
             }
+            decoded_count = SAMPLES_PER_CHUNK;
         }
 
-        // Write it
-        self.sink.write(&samples_to_output)?;
         self.buffer_size = 0;
+        self.block_words_decoded += 1;
+        if self.block_words_decoded * 4 == self.format.channel_block_size() {
+            self.block_words_decoded = 0;
+        }
+
+        // Write the samples whenever we have a full batch
+        let mut offset = 0;
+        while offset < decoded_count {
+            let count = (decoded_count - offset).min(SAMPLES_PER_ADPCM_BLOCK - self.samples_size);
+            for (s, d) in self.samples.iter_mut().zip(decoded.iter()) {
+                s[self.samples_size..self.samples_size + count].copy_from_slice(&d[offset..offset + count]);
+            }
+            self.samples_size += count;
+            offset += count;
+
+            if self.samples_size == SAMPLES_PER_ADPCM_BLOCK {
+                self.sink.write(&self.samples)?;
+                self.samples_size = 0;
+            }
+        }
+
         Ok(())
     }
 }
+
+/// Decode one Xbox ADPCM block for the given number of channels.
+pub(crate) fn decode_block(num_channels: usize, input: &[u8], output: &mut [[i16; SAMPLES_PER_ADPCM_BLOCK]; MAX_AUDIO_CHANNEL_COUNT]) {
+    let mut channels = [ADPCMChannel::default(); MAX_AUDIO_CHANNEL_COUNT];
+
+    // Initialize with the header
+    for (ch, header) in channels[..num_channels].iter_mut().zip(input.chunks_exact(4)) {
+        *ch = read_header(header);
+    }
+
+    // Decode it
+    let words = input[num_channels * 4..ADPCM_BLOCK_SIZE * num_channels].chunks_exact(4);
+    for (w, word) in words.enumerate() {
+        let ch = w % num_channels;
+        let output_offset = w / num_channels * SAMPLES_PER_CHUNK;
+        decode_word(&mut channels[ch], u32::from_le_bytes(word.try_into().unwrap()), &mut output[ch][output_offset..], ADPCMDeltaMode::ShiftAdd);
+    }
+}
+
+/// Decode the eight samples of a word with the arithmetic of the Intel/DVI IMA ADPCM reference decoder, starting with the
+/// lowest four bits.
+///
+/// This is kept separate from [`decode_word`] so that changes to the decoder's own arithmetic cannot change it.
+fn decode_word_ima_reference(channel: &mut ADPCMChannel, mut word: u32, output: &mut [i16]) {
+    for o in output[..SAMPLES_PER_CHUNK].iter_mut() {
+        let delta = (word & 0xF) as usize;
+        word >>= 4;
+
+        // Compute the difference from the magnitude bits, then apply the sign bit
+        let step = STEP_TABLE[channel.index] as i32;
+        let mut vpdiff = step >> 3;
+        if (delta & 4) != 0 { vpdiff += step; }
+        if (delta & 2) != 0 { vpdiff += step >> 1; }
+        if (delta & 1) != 0 { vpdiff += step >> 2; }
+        let valpred = if (delta & 8) != 0 { channel.pcmdata - vpdiff } else { channel.pcmdata + vpdiff };
+
+        // Clamp the sample first, then update and clamp the step index
+        channel.pcmdata = valpred.clamp(i16::MIN as i32, i16::MAX as i32);
+        channel.index = (channel.index as isize + INDEX_TABLE[delta]).clamp(0, STEP_TABLE.len() as isize - 1) as usize;
+        *o = channel.pcmdata as i16;
+    }
+}
@@ -1,3 +1,5 @@
+use core::convert::TryInto;
+
 use crate::*;
 
 /// Writer outputting ADPCM blocks.
@@ -25,11 +27,337 @@ impl XboxADPCMEncodeSink for std::vec::Vec<u8> {
     type Error = ();
 
     fn reserve(&mut self, bytes_amount: usize) -> Result<(), Self::Error> {
-        Ok(self.reserve_exact(bytes_amount))
+        self.reserve_exact(bytes_amount);
+        Ok(())
     }
 
     fn write(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
-        Ok(self.extend_from_slice(&bytes))
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Method used by [`XboxADPCMEncoder::finish`] to fill the unused samples of the last block.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum TailPadding {
+    /// Fill with silence.
+    #[default]
+    Zero,
+
+    /// Repeat the last sample.
+    HoldLastSample,
+
+    /// Fade linearly from the last sample to silence by the end of the block.
+    FadeOut,
+
+    /// Continue with the samples starting at the given loop start position, counted from the first sample passed to
+    /// the encoder.
+    ///
+    /// If the loop is shorter than the padding, the loop is repeated. If the encoder never received the sample at the
+    /// loop start, this falls back to silence.
+    LoopStart(usize)
+}
+
+/// Cost of the error between an input sample and its decoded sample, which the encoder minimizes when choosing each
+/// 4-bit sample.
+///
+/// Only the choice of 4-bit samples is affected, so the output can be decoded the same way with any metric.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum ErrorMetric {
+    /// Squared error, which gives the best signal-to-noise ratio.
+    #[default]
+    Squared,
+
+    /// Squared error after filtering the errors of a channel with `e[n] + (a * e[n - 1] + b * e[n - 2]) / 256`,
+    /// where `[a, b]` are the given coefficients.
+    ///
+    /// The encoder avoids noise at frequencies the filter amplifies and puts up with more noise at frequencies it
+    /// attenuates. This lowers the signal-to-noise ratio, but it can sound better if the noise is moved to where it is
+    /// less audible. Coefficients close to 256 or -256 can add a lot of noise overall. The filter restarts at the start
+    /// of each block, where the header sample has no error.
+    Weighted([i16; 2])
+}
+
+impl ErrorMetric {
+    /// Weighting that avoids noise around a quarter of the sample rate, moving it towards low frequencies and the
+    /// highest frequencies instead.
+    ///
+    /// This filters the error with `e[n] - e[n - 2] / 2`. For 22050 Hz audio, the least noise is at about 5.5 kHz,
+    /// where hearing is the most sensitive.
+    pub const PERCEPTUAL: ErrorMetric = ErrorMetric::Weighted([0, -128]);
+
+    /// Get the cost of the error of a sample, given the errors of the two samples before it.
+    fn cost(self, error: i32, history: [i32; 2]) -> f64 {
+        match self {
+            ErrorMetric::Squared => error.unsigned_abs().pow(2) as f64,
+            ErrorMetric::Weighted([a, b]) => {
+                let filtered = error as f64 + (a as f64 * history[0] as f64 + b as f64 * history[1] as f64) / 256.0;
+                filtered * filtered
+            }
+        }
+    }
+}
+
+/// Settings used when searching for the 4-bit sample with the lowest cost.
+#[derive(Copy, Clone)]
+pub(crate) struct NibbleSearch {
+    /// How deltas are calculated
+    pub delta_mode: ADPCMDeltaMode,
+
+    /// Cost to minimize
+    pub metric: ErrorMetric
+}
+
+/// Loop points of a stream encoded with [`XboxADPCMEncoder::encode_looped`].
+///
+/// All positions are counted in samples per channel as they are output by the decoder.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EncodedLoop {
+    /// Number of samples of silence inserted before the input to move the loop start to a block boundary.
+    pub leading_samples: usize,
+
+    /// Number of times the loop from the input is repeated between the loop start and the loop end, so the loop is a
+    /// whole number of blocks long.
+    pub loop_repetitions: usize,
+
+    /// First sample of the loop. This is always at the start of a block.
+    pub loop_start: usize,
+
+    /// Sample after the last sample of the loop. This is always at the end of a block, and it is also the number of
+    /// decoded samples.
+    pub loop_end: usize,
+
+    /// Index of the block starting at the loop start.
+    pub loop_start_block: usize,
+
+    /// Number of blocks needed to play up to the loop end. This is also the total number of blocks written.
+    pub loop_end_block: usize
+}
+
+/// Progress of an [`XboxADPCMEncoder`], passed to an [`XboxADPCMEncodeObserver`] after each block is encoded.
+///
+/// All counts are since the encoder was last reset.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EncodeProgress {
+    /// Number of samples per channel passed to the encoder
+    pub samples_received: usize,
+
+    /// Number of samples per channel written, counted as they are output by the decoder and including padding
+    pub samples_encoded: usize,
+
+    /// Number of blocks written
+    pub blocks_encoded: usize,
+
+    /// Sum of the squared differences between the input and the decoded samples of all channels, including padding
+    pub squared_error: u64,
+
+    /// Largest difference between an input sample and its decoded sample
+    pub peak_error: u32
+}
+
+impl EncodeProgress {
+    /// Get the mean squared error of all decoded samples so far, or `None` if no samples were written yet.
+    pub fn mean_squared_error(&self, num_channels: usize) -> Option<f64> {
+        let samples = self.samples_encoded * num_channels;
+        if samples == 0 {
+            return None
+        }
+        Some(self.squared_error as f64 / samples as f64)
+    }
+}
+
+/// Information about one channel of a block written by an [`XboxADPCMEncoder`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct EncodedBlockChannelInfo {
+    /// Sample stored in the header
+    pub header_sample: i16,
+
+    /// Step index stored in the header
+    pub step_index: u8,
+
+    /// Number of times each 4-bit sample value was used
+    pub nibble_histogram: [u32; 16],
+
+    /// Sum of the squared differences between the input and the decoded samples
+    pub squared_error: u64,
+
+    /// Largest difference between an input sample and its decoded sample
+    pub peak_error: u32
+}
+
+/// Information about a block written by an [`XboxADPCMEncoder`], passed to an [`XboxADPCMEncodeObserver`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct EncodedBlockInfo {
+    /// Index of the block since the encoder was last reset
+    block: usize,
+
+    /// Information for each channel
+    channels: [EncodedBlockChannelInfo; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Number of channels
+    num_channels: usize
+}
+
+impl EncodedBlockInfo {
+    /// Get the index of the block since the encoder was last reset.
+    pub fn block(&self) -> usize {
+        self.block
+    }
+
+    /// Get the information for each channel.
+    pub fn channels(&self) -> &[EncodedBlockChannelInfo] {
+        &self.channels[..self.num_channels]
+    }
+}
+
+impl Default for EncodedBlockInfo {
+    fn default() -> Self {
+        EncodedBlockInfo {
+            block: 0,
+            channels: [EncodedBlockChannelInfo::default(); MAX_AUDIO_CHANNEL_COUNT],
+            num_channels: 0
+        }
+    }
+}
+
+/// What an [`XboxADPCMEncodeObserver`] wants the encoder to do next.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EncodeControl {
+    /// Keep encoding.
+    Continue,
+
+    /// Stop encoding. See [`XboxADPCMEncoder::set_observer`] for what happens to the encoder.
+    Cancel
+}
+
+/// Observer notified of the progress of an [`XboxADPCMEncoder`].
+///
+/// This is implemented for closures taking an [`EncodeProgress`] and returning an [`EncodeControl`].
+pub trait XboxADPCMEncodeObserver {
+    /// Called after each block is encoded.
+    ///
+    /// With a block layout other than [`ADPCMBlockLayout::Interleaved`], the block may still be held back by the encoder
+    /// until the rest of its group is encoded, so it may not have been written to the sink yet.
+    fn block_encoded(&mut self, progress: &EncodeProgress) -> EncodeControl;
+
+    /// Called with information about each block right before [`XboxADPCMEncodeObserver::block_encoded`].
+    ///
+    /// The information is only collected while an observer is set, so it is incomplete for a block that was already
+    /// started when the observer was set or when the encoder was resumed with [`XboxADPCMEncoder::from_state`].
+    /// Implementing this is optional.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::{EncodeControl, EncodeProgress, EncodedBlockInfo, XboxADPCMEncodeObserver, XboxADPCMEncoder};
+    ///
+    /// struct NibbleLog(Vec<u32>);
+    ///
+    /// impl XboxADPCMEncodeObserver for NibbleLog {
+    ///     fn block_encoded(&mut self, _progress: &EncodeProgress) -> EncodeControl {
+    ///         EncodeControl::Continue
+    ///     }
+    ///
+    ///     fn block_info(&mut self, info: &EncodedBlockInfo) {
+    ///         self.0.push(info.channels()[0].nibble_histogram.iter().sum());
+    ///     }
+    /// }
+    ///
+    /// let samples: Vec<i16> = (0..1000).map(|i| ((i as f64 / 10.0).sin() * 8000.0) as i16).collect();
+    /// let mut log = NibbleLog(Vec::new());
+    /// let mut output = Vec::new();
+    ///
+    /// let mut encoder = XboxADPCMEncoder::new(1, 3, &mut output);
+    /// encoder.set_observer(Some(&mut log));
+    /// encoder.encode(&[&samples]).unwrap();
+    /// encoder.finish().unwrap();
+    ///
+    /// // One entry for each block, each with 64 4-bit samples
+    /// assert_eq!(log.0.len(), output.len() / 36);
+    /// assert!(log.0.iter().all(|&n| n == 64));
+    /// ```
+    #[allow(unused_variables)]
+    fn block_info(&mut self, info: &EncodedBlockInfo) {}
+}
+
+impl<F: FnMut(&EncodeProgress) -> EncodeControl> XboxADPCMEncodeObserver for F {
+    fn block_encoded(&mut self, progress: &EncodeProgress) -> EncodeControl {
+        self(progress)
+    }
+}
+
+/// Error returned by an [`XboxADPCMEncoder`] with verification enabled if a block it wrote does not decode to what
+/// the encoder expected.
+///
+/// See [`XboxADPCMEncoder::set_verify`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct VerifyError {
+    /// Index of the block in the stream
+    pub block: usize,
+
+    /// Channel that did not match
+    pub channel: usize,
+
+    /// Index of the sample in the block, where 0 is the header sample
+    pub sample: usize,
+
+    /// Sample reconstructed by the encoder
+    pub expected_sample: i16,
+
+    /// Sample output by the decoder
+    pub decoded_sample: i16,
+
+    /// Step index reconstructed by the encoder
+    pub expected_step_index: u8,
+
+    /// Step index read or calculated by the decoder
+    pub decoded_step_index: u8
+}
+
+impl core::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "block {} channel {} sample {} decoded to {} (step index {}) instead of {} (step index {})",
+            self.block,
+            self.channel,
+            self.sample,
+            self.decoded_sample,
+            self.decoded_step_index,
+            self.expected_sample,
+            self.expected_step_index
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for VerifyError {}
+
+/// Header sample and step index to use for a block instead of the ones from encoding the previous block.
+#[derive(Copy, Clone)]
+struct PrimedBlock {
+    /// Index of the block to prime
+    block: usize,
+
+    /// Header sample for each channel
+    samples: [i16; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Step index for each channel
+    indices: [usize; MAX_AUDIO_CHANNEL_COUNT]
+}
+
+/// Encode sink that discards everything.
+struct NullEncodeSink;
+
+impl XboxADPCMEncodeSink for NullEncodeSink {
+    type Error = core::convert::Infallible;
+
+    fn write(&mut self, _bytes: &[u8]) -> Result<(), Self::Error> {
+        Ok(())
     }
 }
 
@@ -38,6 +366,8 @@ pub(crate) const PCM_BUFFER_EXTRA: usize = 2;
 pub(crate) const PCM_BUFFER_CAPACITY: usize = SAMPLES_PER_ADPCM_BLOCK + PCM_BUFFER_EXTRA;
 
 /// XboxADPCM encoder implementation.
+///
+/// This also encodes standard IMA ADPCM when initialized with [`XboxADPCMEncoder::with_format`].
 pub struct XboxADPCMEncoder<'a, E> {
     /// Channel data (from adpcm-xq)
     channels: [ADPCMChannel; MAX_AUDIO_CHANNEL_COUNT],
@@ -48,19 +378,234 @@ pub struct XboxADPCMEncoder<'a, E> {
     /// Lookahead value
     lookahead: usize,
 
-    /// Buffer containing the next samples to be processed
+    /// Block format
+    format: ADPCMBlockFormat,
+
+    /// How sample deltas are calculated
+    delta_mode: ADPCMDeltaMode,
+
+    /// Block layout
+    layout: ADPCMBlockLayout,
+
+    /// Interleaved blocks not yet written to the sink in the block layout
+    layout_buffer: LayoutBuffer,
+
+    /// Buffer containing the next samples to be processed, starting with the last sample encoded in the current block
     buffer: [[i16; PCM_BUFFER_CAPACITY]; MAX_AUDIO_CHANNEL_COUNT],
 
     /// Current size of the buffer
     buffer_size: usize,
 
+    /// Position of the first sample in the buffer, counted from the first sample passed to the encoder
+    buffer_position: usize,
+
+    /// Number of 4-bit samples per channel encoded in the current block, or `None` if the header was not written yet
+    block_samples_encoded: Option<usize>,
+
+    /// Encoded bytes not yet written to the sink
+    output: [u8; ADPCM_BLOCK_SIZE * MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Number of bytes in `output`
+    output_size: usize,
+
     /// Did we initialize the predictors?
     predictors_initialized: bool,
 
+    /// Samples of an interleaved frame that was split across calls
+    partial_frame: [i16; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Number of samples in the partial frame
+    partial_frame_size: usize,
+
+    /// Low byte of a little endian sample that was split across calls
+    partial_byte: Option<u8>,
+
+    /// How to pad the last block
+    tail_padding: TailPadding,
+
+    /// Number of samples per channel loaded into the buffer since the last reset
+    samples_received: usize,
+
+    /// Samples starting at the loop start, used for padding with [`TailPadding::LoopStart`]
+    loop_samples: [[i16; PCM_BUFFER_CAPACITY]; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Number of samples in `loop_samples`
+    loop_samples_size: usize,
+
+    /// Number of blocks encoded since the last reset
+    blocks_encoded: usize,
+
+    /// Block to prime with a different header
+    primed_block: Option<PrimedBlock>,
+
+    /// Position of a sample to record the step indices of after encoding it
+    index_probe: Option<usize>,
+
+    /// Step indices recorded for `index_probe`
+    probed_indices: [usize; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Number of bytes written to the sink since the last reset
+    bytes_written: usize,
+
+    /// Sum of the squared differences between the input and the decoded samples since the last reset
+    squared_error: u64,
+
+    /// Largest difference between an input sample and its decoded sample since the last reset
+    peak_error: u32,
+
+    /// Information about the block being encoded, only collected if there is an observer
+    block_info: EncodedBlockInfo,
+
+    /// Observer notified after each block
+    observer: Option<&'a mut dyn XboxADPCMEncodeObserver>,
+
+    /// Did the observer cancel encoding during the current call?
+    cancelled: bool,
+
+    /// Did the observer cancel the last call to an encoding function? Unlike `cancelled`, this is not cleared by resetting.
+    last_call_cancelled: bool,
+
+    /// Cost minimized when choosing samples
+    error_metric: ErrorMetric,
+
+    /// Errors of the last two samples encoded for each channel, most recent first
+    error_history: [[i32; 2]; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Conversion of verification errors to sink errors if verification is enabled
+    verify: Option<fn(VerifyError) -> E>,
+
+    /// Decoder state of each channel, decoded from the written output
+    verify_channels: [ADPCMChannel; MAX_AUDIO_CHANNEL_COUNT],
+
     /// Output buffer
     sink: &'a mut dyn XboxADPCMEncodeSink<Error = E>
 }
 
+/// Saved state of an [`XboxADPCMEncoder`], including its configuration, the predictor and step index of each channel,
+/// samples and bytes that were not encoded or written yet, and statistics.
+///
+/// This can be serialized with serde if the `"serde"` feature is enabled, so a long encode can be resumed in another
+/// process with [`XboxADPCMEncoder::from_state`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct XboxADPCMEncoderState {
+    channels: [ADPCMChannel; MAX_AUDIO_CHANNEL_COUNT],
+    num_channels: usize,
+    lookahead: usize,
+    format: ADPCMBlockFormat,
+    delta_mode: ADPCMDeltaMode,
+    layout: ADPCMBlockLayout,
+    layout_buffer: LayoutBuffer,
+    #[cfg_attr(feature = "serde", serde(with = "serde_arrays::nested"))]
+    buffer: [[i16; PCM_BUFFER_CAPACITY]; MAX_AUDIO_CHANNEL_COUNT],
+    buffer_size: usize,
+    buffer_position: usize,
+    block_samples_encoded: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_arrays::flat"))]
+    output: [u8; ADPCM_BLOCK_SIZE * MAX_AUDIO_CHANNEL_COUNT],
+    output_size: usize,
+    predictors_initialized: bool,
+    partial_frame: [i16; MAX_AUDIO_CHANNEL_COUNT],
+    partial_frame_size: usize,
+    partial_byte: Option<u8>,
+    tail_padding: TailPadding,
+    samples_received: usize,
+    #[cfg_attr(feature = "serde", serde(with = "serde_arrays::nested"))]
+    loop_samples: [[i16; PCM_BUFFER_CAPACITY]; MAX_AUDIO_CHANNEL_COUNT],
+    loop_samples_size: usize,
+    blocks_encoded: usize,
+    bytes_written: usize,
+    squared_error: u64,
+    peak_error: u32,
+    verify_channels: [ADPCMChannel; MAX_AUDIO_CHANNEL_COUNT],
+    error_metric: ErrorMetric,
+    error_history: [[i32; 2]; MAX_AUDIO_CHANNEL_COUNT]
+}
+
+impl XboxADPCMEncoderState {
+    /// Get the number of samples per channel passed to the encoder since it was last reset, not counting samples of an
+    /// incomplete interleaved frame.
+    pub fn samples_received(&self) -> usize {
+        self.samples_received
+    }
+
+    /// Get the number of blocks encoded since the encoder was last reset.
+    pub fn blocks_encoded(&self) -> usize {
+        self.blocks_encoded
+    }
+
+    /// Get the number of bytes written to the sink since the encoder was last reset.
+    pub fn bytes_written(&self) -> usize {
+        self.bytes_written
+    }
+
+    /// Check that the state could have come from an encoder, since it may have been deserialized from anywhere.
+    fn is_valid(&self) -> bool {
+        let num_channels_valid = self.num_channels > 0 && self.num_channels <= MAX_AUDIO_CHANNEL_COUNT;
+
+        num_channels_valid
+            && self.layout_valid()
+            && self.lookahead <= u8::MAX as usize
+            && self.format.is_valid()
+            && self.buffer_size <= PCM_BUFFER_CAPACITY
+            && self.output_size <= self.output.len()
+            && self.partial_frame_size < self.num_channels
+            && self.loop_samples_size <= PCM_BUFFER_CAPACITY
+            && self.channels.iter().all(ADPCMChannel::is_valid)
+            && self.verify_channels.iter().all(ADPCMChannel::is_valid)
+            && self.position_valid()
+    }
+
+    /// Check that the position in the block agrees with the samples buffered and the bytes written.
+    fn position_valid(&self) -> bool {
+        let nibbles_per_block = self.format.nibbles_per_block();
+        let block_samples_encoded = self.block_samples_encoded.unwrap_or(0);
+        let block_bytes = match self.block_samples_encoded {
+            // A started block keeps its last sample encoded in the buffer, and its header needed the predictors.
+            Some(s) if s < nibbles_per_block && s % SAMPLES_PER_CHUNK == 0 && self.buffer_size > 0 && self.predictors_initialized => {
+                4 * self.num_channels * (1 + s / SAMPLES_PER_CHUNK)
+            },
+            Some(_) => return false,
+            None => 0
+        };
+
+        let position = self.blocks_encoded
+            .checked_mul(self.format.samples_per_block())
+            .and_then(|p| p.checked_add(block_samples_encoded));
+        let bytes_encoded = self.blocks_encoded
+            .checked_mul(self.format.block_size(self.num_channels))
+            .and_then(|b| b.checked_add(block_bytes));
+        let bytes_held = self.bytes_written
+            .checked_add(self.output_size)
+            .and_then(|b| b.checked_add(self.layout_buffer.len()));
+
+        // Encoding stops as soon as the buffer has enough samples for the next chunk, so it never holds more samples
+        // than are left in the block.
+        (self.blocks_encoded == 0 || self.predictors_initialized)
+            && self.buffer_size + block_samples_encoded <= nibbles_per_block + 1
+            && self.buffer_position.checked_add(self.buffer_size) == Some(self.samples_received)
+            && position == Some(self.buffer_position)
+            && bytes_encoded.is_some()
+            && bytes_encoded == bytes_held
+    }
+
+    /// Check that the layout is supported and the bytes held back for it are less than a group.
+    #[cfg(feature = "std")]
+    fn layout_valid(&self) -> bool {
+        match self.layout {
+            ADPCMBlockLayout::Interleaved => self.layout_buffer.is_empty(),
+            ADPCMBlockLayout::Planar => true,
+            ADPCMBlockLayout::Stride(n) => n > 0 && self.layout_buffer.len() < n.saturating_mul(self.format.block_size(self.num_channels))
+        }
+    }
+
+    /// Check that the layout is supported, which without std is only the interleaved layout.
+    #[cfg(not(feature = "std"))]
+    fn layout_valid(&self) -> bool {
+        self.layout == ADPCMBlockLayout::Interleaved
+    }
+}
+
 impl<'a, E> XboxADPCMEncoder<'a, E> where E: Sized {
     /// Initialize an encoder with the given channel count, and lookahead for the given sink.
     ///
@@ -70,19 +615,194 @@ impl<'a, E> XboxADPCMEncoder<'a, E> where E: Sized {
     ///
     /// Panics if `num_channels` is not between 1 and 8
     pub fn new(num_channels: usize, lookahead: u8, sink: &'a mut dyn XboxADPCMEncodeSink<Error = E>) -> XboxADPCMEncoder<'a, E> {
+        XboxADPCMEncoder::with_format(num_channels, lookahead, ADPCMBlockFormat::XBOX, sink)
+    }
+
+    /// Initialize an encoder with the given channel count, lookahead and block format for the given sink.
+    ///
+    /// Lookahead does not see past the end of a block, and it is limited to 57 samples.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_channels` is not between 1 and 8
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::{ADPCMBlockFormat, XboxADPCMEncoder};
+    ///
+    /// let samples = [1000i16; 2000];
+    /// let format = ADPCMBlockFormat::ima(1024, 2).unwrap();
+    /// let mut output = Vec::new();
+    ///
+    /// let mut encoder = XboxADPCMEncoder::with_format(2, 3, format, &mut output);
+    /// encoder.encode(&[&samples, &samples]).unwrap();
+    ///
+    /// // 1017 samples per block, the first of them stored in the header
+    /// assert_eq!(encoder.finish().unwrap(), 2000 - 1017);
+    /// assert_eq!(output.len(), 1024 * 2);
+    /// ```
+    pub fn with_format(num_channels: usize, lookahead: u8, format: ADPCMBlockFormat, sink: &'a mut dyn XboxADPCMEncodeSink<Error = E>) -> XboxADPCMEncoder<'a, E> {
         assert!(num_channels > 0 && num_channels <= MAX_AUDIO_CHANNEL_COUNT, "num_channels must be between 1 and {}", MAX_AUDIO_CHANNEL_COUNT);
 
         XboxADPCMEncoder {
             channels: <[ADPCMChannel; MAX_AUDIO_CHANNEL_COUNT]>::default(),
             num_channels,
             lookahead: lookahead as usize,
+            format,
+            delta_mode: ADPCMDeltaMode::ShiftAdd,
+            layout: ADPCMBlockLayout::Interleaved,
+            layout_buffer: LayoutBuffer::new(),
             buffer_size: 0,
             buffer: [[0i16; PCM_BUFFER_CAPACITY]; MAX_AUDIO_CHANNEL_COUNT],
+            buffer_position: 0,
+            block_samples_encoded: None,
+            output: [0u8; ADPCM_BLOCK_SIZE * MAX_AUDIO_CHANNEL_COUNT],
+            output_size: 0,
             predictors_initialized: false,
+            partial_frame: [0i16; MAX_AUDIO_CHANNEL_COUNT],
+            partial_frame_size: 0,
+            partial_byte: None,
+            tail_padding: TailPadding::Zero,
+            samples_received: 0,
+            loop_samples: [[0i16; PCM_BUFFER_CAPACITY]; MAX_AUDIO_CHANNEL_COUNT],
+            loop_samples_size: 0,
+            blocks_encoded: 0,
+            primed_block: None,
+            index_probe: None,
+            probed_indices: [0usize; MAX_AUDIO_CHANNEL_COUNT],
+            bytes_written: 0,
+            squared_error: 0,
+            peak_error: 0,
+            block_info: EncodedBlockInfo::default(),
+            observer: None,
+            cancelled: false,
+            last_call_cancelled: false,
+            error_metric: ErrorMetric::Squared,
+            error_history: [[0i32; 2]; MAX_AUDIO_CHANNEL_COUNT],
+            verify: None,
+            verify_channels: <[ADPCMChannel; MAX_AUDIO_CHANNEL_COUNT]>::default(),
             sink
         }
     }
 
+    /// Initialize an encoder from a saved state, writing to the given output.
+    ///
+    /// The output should have the first [`XboxADPCMEncoderState::bytes_written`] bytes the encoder wrote before the
+    /// state was saved, and nothing after them.
+    ///
+    /// Verification is not enabled even if it was enabled for the encoder the state was saved from.
+    ///
+    /// Returns `None` if the state is invalid, which can only happen if it was deserialized from invalid data.
+    pub fn from_state(state: &XboxADPCMEncoderState, sink: &'a mut dyn XboxADPCMEncodeSink<Error = E>) -> Option<XboxADPCMEncoder<'a, E>> {
+        if !state.is_valid() {
+            return None
+        }
+
+        Some(XboxADPCMEncoder {
+            channels: state.channels,
+            num_channels: state.num_channels,
+            lookahead: state.lookahead,
+            format: state.format,
+            delta_mode: state.delta_mode,
+            layout: state.layout,
+            layout_buffer: state.layout_buffer.clone(),
+            buffer: state.buffer,
+            buffer_size: state.buffer_size,
+            buffer_position: state.buffer_position,
+            block_samples_encoded: state.block_samples_encoded,
+            output: state.output,
+            output_size: state.output_size,
+            predictors_initialized: state.predictors_initialized,
+            partial_frame: state.partial_frame,
+            partial_frame_size: state.partial_frame_size,
+            partial_byte: state.partial_byte,
+            tail_padding: state.tail_padding,
+            samples_received: state.samples_received,
+            loop_samples: state.loop_samples,
+            loop_samples_size: state.loop_samples_size,
+            blocks_encoded: state.blocks_encoded,
+            primed_block: None,
+            index_probe: None,
+            probed_indices: [0usize; MAX_AUDIO_CHANNEL_COUNT],
+            bytes_written: state.bytes_written,
+            squared_error: state.squared_error,
+            peak_error: state.peak_error,
+            block_info: EncodedBlockInfo::default(),
+            observer: None,
+            cancelled: false,
+            last_call_cancelled: false,
+            error_metric: state.error_metric,
+            error_history: state.error_history,
+            verify: None,
+            verify_channels: state.verify_channels,
+            sink
+        })
+    }
+
+    /// Save the state of the encoder.
+    ///
+    /// Encoding with an encoder restored with [`XboxADPCMEncoder::from_state`] writes exactly the same bytes as this
+    /// encoder would have.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::XboxADPCMEncoder;
+    ///
+    /// let samples: Vec<i16> = (0..1000).map(|i| ((i as f64 / 10.0).sin() * 8000.0) as i16).collect();
+    ///
+    /// let mut output = Vec::new();
+    /// let mut encoder = XboxADPCMEncoder::new(1, 3, &mut output);
+    /// encoder.encode(&[&samples[..300]]).unwrap();
+    /// let state = encoder.state();
+    ///
+    /// // Pretend the encoder crashed while encoding the rest, and resume from the saved state.
+    /// output.truncate(state.bytes_written());
+    /// let mut encoder = XboxADPCMEncoder::from_state(&state, &mut output).unwrap();
+    /// encoder.encode(&[&samples[300..]]).unwrap();
+    /// encoder.finish().unwrap();
+    ///
+    /// let mut uninterrupted_output = Vec::new();
+    /// let mut encoder = XboxADPCMEncoder::new(1, 3, &mut uninterrupted_output);
+    /// encoder.encode(&[&samples]).unwrap();
+    /// encoder.finish().unwrap();
+    ///
+    /// assert_eq!(output, uninterrupted_output);
+    /// ```
+    pub fn state(&self) -> XboxADPCMEncoderState {
+        XboxADPCMEncoderState {
+            channels: self.channels,
+            num_channels: self.num_channels,
+            lookahead: self.lookahead,
+            format: self.format,
+            delta_mode: self.delta_mode,
+            layout: self.layout,
+            layout_buffer: self.layout_buffer.clone(),
+            buffer: self.buffer,
+            buffer_size: self.buffer_size,
+            buffer_position: self.buffer_position,
+            block_samples_encoded: self.block_samples_encoded,
+            output: self.output,
+            output_size: self.output_size,
+            predictors_initialized: self.predictors_initialized,
+            partial_frame: self.partial_frame,
+            partial_frame_size: self.partial_frame_size,
+            partial_byte: self.partial_byte,
+            tail_padding: self.tail_padding,
+            samples_received: self.samples_received,
+            loop_samples: self.loop_samples,
+            loop_samples_size: self.loop_samples_size,
+            blocks_encoded: self.blocks_encoded,
+            bytes_written: self.bytes_written,
+            squared_error: self.squared_error,
+            peak_error: self.peak_error,
+            verify_channels: self.verify_channels,
+            error_metric: self.error_metric,
+            error_history: self.error_history
+        }
+    }
+
     /// Encode with the given samples using some samples.
     ///
     /// Note that this may not always encode all samples passed and may store some in a buffer. To flush the buffer, run [`XboxADPCMEncoder::finish`].
@@ -95,41 +815,316 @@ impl<'a, E> XboxADPCMEncoder<'a, E> where E: Sized {
         assert_eq!(self.num_channels, input_arr.len(), "input channel count is incorrect");
 
         let sample_count = input_arr[0].as_ref().len();
-        for i in 1..self.num_channels {
-            assert_eq!(sample_count, input_arr[i].as_ref().len(), "sample count of channel {i} does not match the sample count of channel 0");
+        for (i, channel) in input_arr.iter().enumerate().skip(1) {
+            assert_eq!(sample_count, channel.as_ref().len(), "sample count of channel {i} does not match the sample count of channel 0");
+        }
+
+        let result = self.reserve_samples(sample_count)
+            .and_then(|_| self.load_samples(sample_count, |c, i| input_arr[c].as_ref()[i]));
+        self.reset_if_cancelled(result)
+    }
+
+    /// Encode the given samples as a loop and then finish encoding.
+    ///
+    /// `loop_start` and `loop_end` are the first sample of the loop and the sample after the last sample of the loop in
+    /// the input. Samples after the loop end are not encoded.
+    ///
+    /// Both loop points are moved to block boundaries. Silence is inserted before the input so the loop start falls on
+    /// a block boundary, and the loop is repeated until its length is a whole number of blocks, so the loop end falls on
+    /// one too. This can repeat the loop up to once for every sample in a block, so loops that are already a multiple of
+    /// [`ADPCMBlockFormat::samples_per_block`] long stay the smallest. Jumping from the loop end back to the loop start
+    /// then decodes the block at the loop start from its header, exactly as the first pass through it did, so the
+    /// wraparound is as continuous as the input loop.
+    ///
+    /// The block at the loop start is primed with the last sample of the loop and the step index the encoder reaches
+    /// at the loop end, so it is encoded as continuing from the loop end. For formats that output the header sample,
+    /// the header keeps the sample at the loop start and only the step index is primed. The step index is found by
+    /// encoding the loop once before encoding it for real, so this takes about twice as long as
+    /// [`XboxADPCMEncoder::encode`].
+    ///
+    /// Returns the block-aligned loop points, counted in decoded samples, or `None` if the observer cancelled encoding
+    /// (see [`XboxADPCMEncoder::set_observer`]).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the input has the wrong number of channels or the samples are wrong, if the loop points are not inside
+    /// the input, or if the encoder has samples that were not finished yet.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::XboxADPCMEncoder;
+    ///
+    /// let samples: Vec<i16> = (0..1000).map(|i| ((i as f64 / 10.0).sin() * 8000.0) as i16).collect();
+    /// let mut output = Vec::new();
+    ///
+    /// let mut encoder = XboxADPCMEncoder::new(1, 3, &mut output);
+    /// let encoded_loop = encoder.encode_looped(&[&samples], 100, 900).unwrap().unwrap();
+    ///
+    /// // The 800 sample loop is repeated twice to be a whole number of 64 sample blocks.
+    /// assert_eq!(encoded_loop.loop_start % 64, 0);
+    /// assert_eq!(encoded_loop.loop_repetitions, 2);
+    /// assert_eq!(encoded_loop.loop_end - encoded_loop.loop_start, 1600);
+    /// assert_eq!(output.len(), encoded_loop.loop_end_block * 36);
+    /// ```
+    pub fn encode_looped<B: AsRef<[C]>, C: AsRef<[i16]>>(&mut self, input: B, loop_start: usize, loop_end: usize) -> Result<Option<EncodedLoop>, E> {
+        let input_arr = input.as_ref();
+        assert_eq!(self.num_channels, input_arr.len(), "input channel count is incorrect");
+
+        let sample_count = input_arr[0].as_ref().len();
+        for (i, channel) in input_arr.iter().enumerate().skip(1) {
+            assert_eq!(sample_count, channel.as_ref().len(), "sample count of channel {i} does not match the sample count of channel 0");
+        }
+        assert!(loop_start < loop_end && loop_end <= sample_count, "loop points must be inside the input");
+        assert!(self.samples_received == 0 && self.partial_frame_size == 0, "encoder has unfinished samples");
+
+        // Unless the header sample is output, the first sample is only stored in the header, so the sample before the
+        // loop start has to be the last sample of a block.
+        let samples_per_block = self.format.samples_per_block();
+        let header_offset = !self.format.outputs_header_sample() as usize;
+        let leading_samples = (header_offset as isize - loop_start as isize).rem_euclid(samples_per_block as isize) as usize;
+        let loop_start_decoded = leading_samples + loop_start - header_offset;
+        let loop_start_block = loop_start_decoded / samples_per_block;
+
+        let loop_length = loop_end - loop_start;
+        let mut loop_repetitions = 1;
+        while loop_length * loop_repetitions % samples_per_block != 0 {
+            loop_repetitions += 1;
+        }
+        let loop_end_decoded = loop_start_decoded + loop_length * loop_repetitions;
+
+        // Find the step indices at the end of the loop.
+        let mut null_sink = NullEncodeSink;
+        let mut probe = self.probe_encoder(&mut null_sink);
+        probe.index_probe = Some(leading_samples + loop_start + loop_length * loop_repetitions - 1);
+        let Ok(()) = probe.encode_loop(input_arr, leading_samples, loop_start, loop_end, loop_repetitions);
+        let Ok(_) = probe.finish();
+
+        let header_sample = if header_offset == 0 { loop_start } else { loop_end - 1 };
+        let mut samples = [0i16; MAX_AUDIO_CHANNEL_COUNT];
+        for (s, c) in samples.iter_mut().zip(input_arr.iter()) {
+            *s = c.as_ref()[header_sample];
+        }
+
+        self.primed_block = Some(PrimedBlock { block: loop_start_block, samples, indices: probe.probed_indices });
+
+        // The last block ends at the loop end, so it is never padded.
+        let result = self.encode_loop(input_arr, leading_samples, loop_start, loop_end, loop_repetitions).and_then(|_| if self.cancelled { Ok(0) } else { self.finish() });
+        self.reset_if_cancelled(result.map(|_| ()))?;
+        if self.last_call_cancelled {
+            return Ok(None)
+        }
+
+        Ok(Some(EncodedLoop {
+            leading_samples,
+            loop_repetitions,
+            loop_start: loop_start_decoded,
+            loop_end: loop_end_decoded,
+            loop_start_block,
+            loop_end_block: loop_end_decoded / samples_per_block
+        }))
+    }
+
+    /// Create an encoder that discards its output and chooses the same 4-bit samples as this one, for
+    /// [`XboxADPCMEncoder::encode_looped`] to encode the loop with before encoding it for real.
+    fn probe_encoder<'b>(&self, null_sink: &'b mut NullEncodeSink) -> XboxADPCMEncoder<'b, core::convert::Infallible> {
+        let mut probe = XboxADPCMEncoder::with_format(self.num_channels, self.lookahead as u8, self.format, null_sink);
+        probe.delta_mode = self.delta_mode;
+        probe.error_metric = self.error_metric;
+        probe
+    }
+
+    /// Load the samples for [`XboxADPCMEncoder::encode_looped`], repeating the loop the given number of times.
+    fn encode_loop<C: AsRef<[i16]>>(&mut self, input: &[C], leading_samples: usize, loop_start: usize, loop_end: usize, loop_repetitions: usize) -> Result<(), E> {
+        let loop_length = loop_end - loop_start;
+        let repeated_samples = loop_length * (loop_repetitions - 1);
+        self.reserve_samples(leading_samples + loop_end + repeated_samples)?;
+        self.load_samples(leading_samples, |_, _| 0)?;
+        self.load_samples(loop_end, |c, i| input[c].as_ref()[i])?;
+        self.load_samples(repeated_samples, |c, i| input[c].as_ref()[loop_start + i % loop_length])
+    }
+
+    /// Encode the given floating point samples, converting them to 16-bit samples with `converter`.
+    ///
+    /// Samples are expected to be normalized to ±1.0. This otherwise works the same as [`XboxADPCMEncoder::encode`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the input has the wrong number of channels or the samples are wrong.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::{XboxADPCMEncoder, FloatConverter, FloatConversionMode};
+    ///
+    /// let samples = [0.0f32, 0.25, 0.5, 1.5, -0.5];
+    /// let mut output = Vec::new();
+    /// let mut converter = FloatConverter::with_seed(FloatConversionMode::TriangularDither, 1234);
+    ///
+    /// let mut encoder = XboxADPCMEncoder::new(1, 3, &mut output);
+    /// encoder.encode_float(&[&samples], &mut converter).unwrap();
+    /// encoder.finish().unwrap();
+    ///
+    /// assert_eq!(converter.clipped_samples(), 1);
+    /// ```
+    pub fn encode_float<B: AsRef<[C]>, C: AsRef<[F]>, F: FloatSample>(&mut self, input: B, converter: &mut FloatConverter) -> Result<(), E> {
+        let input_arr = input.as_ref();
+        assert_eq!(self.num_channels, input_arr.len(), "input channel count is incorrect");
+
+        let sample_count = input_arr[0].as_ref().len();
+        for (i, channel) in input_arr.iter().enumerate().skip(1) {
+            assert_eq!(sample_count, channel.as_ref().len(), "sample count of channel {i} does not match the sample count of channel 0");
         }
 
+        let result = self.reserve_samples(sample_count)
+            .and_then(|_| self.load_samples(sample_count, |c, i| converter.convert(input_arr[c].as_ref()[i])));
+        self.reset_if_cancelled(result)
+    }
+
+    /// Encode the given interleaved samples.
+    ///
+    /// The input does not need to contain a whole number of frames. Any samples of an incomplete frame at the end are
+    /// kept until the rest of the frame is passed in the next call. This otherwise works the same as
+    /// [`XboxADPCMEncoder::encode`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::XboxADPCMEncoder;
+    ///
+    /// let interleaved = [0i16, 0, 100, -100, 200, -200];
+    /// let mut output = Vec::new();
+    ///
+    /// let mut encoder = XboxADPCMEncoder::new(2, 3, &mut output);
+    ///
+    /// // The second frame is split across both calls
+    /// encoder.encode_interleaved(&interleaved[..3]).unwrap();
+    /// encoder.encode_interleaved(&interleaved[3..]).unwrap();
+    /// encoder.finish().unwrap();
+    ///
+    /// assert_eq!(output.len(), 72);
+    /// ```
+    pub fn encode_interleaved(&mut self, input: &[i16]) -> Result<(), E> {
+        let result = self.reserve_samples((self.partial_frame_size + input.len()) / self.num_channels)
+            .and_then(|_| self.load_interleaved_samples(input.len(), |i| input[i]));
+        self.reset_if_cancelled(result)
+    }
+
+    /// Encode the given interleaved samples stored as 16-bit little endian integers.
+    ///
+    /// The input does not need to contain a whole number of samples or frames. Any bytes of an incomplete sample or
+    /// frame at the end are kept until the rest is passed in the next call. This otherwise works the same as
+    /// [`XboxADPCMEncoder::encode`].
+    pub fn encode_interleaved_le_bytes(&mut self, input: &[u8]) -> Result<(), E> {
+        let pending_byte = self.partial_byte.take();
+        let byte = |i: usize| match pending_byte {
+            Some(b) if i == 0 => b,
+            Some(_) => input[i - 1],
+            None => input[i]
+        };
+
+        let byte_count = input.len() + pending_byte.is_some() as usize;
+        let sample_count = byte_count / 2;
+
+        let result = self.reserve_samples((self.partial_frame_size + sample_count) / self.num_channels)
+            .and_then(|_| self.load_interleaved_samples(sample_count, |i| i16::from_le_bytes([byte(i * 2), byte(i * 2 + 1)])));
+
+        if byte_count % 2 == 1 {
+            self.partial_byte = Some(byte(byte_count - 1));
+        }
+
+        self.reset_if_cancelled(result)
+    }
+
+    /// Encode the given interleaved floating point samples, converting them to 16-bit samples with `converter`.
+    ///
+    /// Samples are expected to be normalized to ±1.0. This otherwise works the same as
+    /// [`XboxADPCMEncoder::encode_interleaved`].
+    pub fn encode_float_interleaved<F: FloatSample>(&mut self, input: &[F], converter: &mut FloatConverter) -> Result<(), E> {
+        let result = self.reserve_samples((self.partial_frame_size + input.len()) / self.num_channels)
+            .and_then(|_| self.load_interleaved_samples(input.len(), |i| converter.convert(input[i])));
+        self.reset_if_cancelled(result)
+    }
+
+    /// Reserve enough bytes in the sink to encode the given number of samples per channel.
+    fn reserve_samples(&mut self, sample_count: usize) -> Result<(), E> {
         // Calculate how many samples we will process.
         let total_samples_after_this = sample_count + self.buffer_size;
 
-        // Predict how many bytes we will need to reserve, always rounding up to the next block.
+        // Predict how many bytes we will need to reserve, including the last block we pad when finishing.
         //
         // If we have any samples, we need at least one block even if we may not immediately encode them yet.
         if total_samples_after_this != 0 {
-            self.sink.reserve((total_samples_after_this + (SAMPLES_PER_ADPCM_BLOCK - 1)) / SAMPLES_PER_ADPCM_BLOCK * ADPCM_BLOCK_SIZE * self.num_channels)?;
+            self.sink.reserve(self.format.encoded_size(total_samples_after_this, self.num_channels))?;
         }
 
-        // Process all samples.
+        Ok(())
+    }
+
+    /// Load the given number of interleaved samples into the buffer, keeping any incomplete frame at the end.
+    ///
+    /// `sample` is called with the index of each interleaved sample to get it.
+    fn load_interleaved_samples(&mut self, sample_count: usize, mut sample: impl FnMut(usize) -> i16) -> Result<(), E> {
+        let num_channels = self.num_channels;
+        let mut offset = 0;
+
+        // Complete a frame from the previous call first.
+        if self.partial_frame_size != 0 {
+            while self.partial_frame_size < num_channels && offset < sample_count {
+                self.partial_frame[self.partial_frame_size] = sample(offset);
+                self.partial_frame_size += 1;
+                offset += 1;
+            }
+            if self.partial_frame_size < num_channels {
+                return Ok(())
+            }
+
+            let frame = self.partial_frame;
+            self.partial_frame_size = 0;
+            self.load_samples(1, |c, _| frame[c])?;
+        }
+
+        // Load all whole frames.
+        let frame_count = (sample_count - offset) / num_channels;
+        self.load_samples(frame_count, |c, i| sample(offset + i * num_channels + c))?;
+        offset += frame_count * num_channels;
+
+        // Keep what is left for the next call.
+        while offset < sample_count {
+            self.partial_frame[self.partial_frame_size] = sample(offset);
+            self.partial_frame_size += 1;
+            offset += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Load the given number of samples per channel into the buffer, encoding them as the buffer fills.
+    ///
+    /// `sample` is called with the channel and the sample index to get each sample. Samples are requested one frame at a
+    /// time, the same order as interleaved input, so stateful conversions like dithering do not depend on how the input
+    /// is split.
+    fn load_samples(&mut self, sample_count: usize, mut sample: impl FnMut(usize, usize) -> i16) -> Result<(), E> {
         let mut samples_loaded = 0;
-        while samples_loaded != sample_count {
+        while samples_loaded != sample_count && !self.cancelled {
             let samples_left_to_load = sample_count - samples_loaded;
             let samples_free = PCM_BUFFER_CAPACITY - self.buffer_size;
             let samples_that_can_be_loaded = samples_free.min(samples_left_to_load);
-            for c in 0..self.num_channels {
-                let input_samples = &input_arr[c].as_ref()[samples_loaded..];
-                let buff_samples = &mut self.buffer[c][self.buffer_size..];
-                for i in 0..samples_that_can_be_loaded {
-                    buff_samples[i] = input_samples[i];
+            for i in 0..samples_that_can_be_loaded {
+                for c in 0..self.num_channels {
+                    self.buffer[c][self.buffer_size + i] = sample(c, samples_loaded + i);
                 }
             }
 
+            if let TailPadding::LoopStart(loop_start) = self.tail_padding {
+                self.capture_loop_samples(loop_start, samples_that_can_be_loaded);
+            }
+
             samples_loaded += samples_that_can_be_loaded;
             self.buffer_size += samples_that_can_be_loaded;
+            self.samples_received += samples_that_can_be_loaded;
 
-            if self.buffer_size == PCM_BUFFER_CAPACITY {
-                self.initialize_predictors();
-                self.encode_block()?;
-            }
+            self.encode_buffer()?;
         }
 
         Ok(())
@@ -137,25 +1132,118 @@ impl<'a, E> XboxADPCMEncoder<'a, E> where E: Sized {
 
     /// Finish encoding and then resets the encoder.
     ///
-    /// This will encode all remaining samples, filling any unused samples with silence. If a simple reset is desired without any further writes, call [`XboxADPCMEncoder::reset`] instead.
-    pub fn finish(&mut self) -> Result<(), E> {
-        if self.buffer_size != 0 {
+    /// This will encode all remaining samples, filling any unused samples as set by
+    /// [`XboxADPCMEncoder::set_tail_padding`]. An incomplete interleaved frame is discarded. If a simple reset is desired
+    /// without any further writes, call [`XboxADPCMEncoder::reset`] instead.
+    ///
+    /// Returns the number of samples per channel in the last block that came from the input, counted as they are output
+    /// by the decoder. The rest of the block is padding. If no block was written, this returns 0.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::{XboxADPCMEncoder, TailPadding};
+    ///
+    /// let samples = [1000i16; 100];
+    /// let mut output = Vec::new();
+    ///
+    /// let mut encoder = XboxADPCMEncoder::new(1, 3, &mut output);
+    /// encoder.set_tail_padding(TailPadding::HoldLastSample);
+    /// encoder.encode(&[&samples]).unwrap();
+    ///
+    /// // The first block has 64 samples, and the first sample is only stored in the header.
+    /// assert_eq!(encoder.finish().unwrap(), 100 - 1 - 64);
+    /// ```
+    pub fn finish(&mut self) -> Result<usize, E> {
+        let mut real_samples = 0;
+
+        // The first sample in the buffer was already encoded if it was the last sample of the previous block or the
+        // header of this one, unless the header sample is output.
+        let block_started = self.block_samples_encoded.is_some();
+        let encoded_samples_in_buffer = (block_started || (self.blocks_encoded != 0 && !self.format.outputs_header_sample())) as usize;
+
+        if block_started || self.buffer_size > encoded_samples_in_buffer {
             // Init predictors
             self.initialize_predictors();
 
-            // Zero-out everything at the end and set our buffer size.
-            for c in &mut self.buffer[0..self.num_channels] {
-                for b in &mut c[self.buffer_size..PCM_BUFFER_CAPACITY] {
-                    *b = 0;
-                }
+            let block_samples_encoded = self.block_samples_encoded.unwrap_or(0);
+            real_samples = self.format.outputs_header_sample() as usize + block_samples_encoded + self.buffer_size - 1;
+
+            // Pad everything up to the end of the block and encode it.
+            let mut last_samples = [0i16; MAX_AUDIO_CHANNEL_COUNT];
+            for (l, c) in last_samples.iter_mut().zip(self.buffer.iter()) {
+                *l = c[self.buffer_size - 1];
             }
-            self.buffer_size = PCM_BUFFER_CAPACITY;
+            let fade_length = (self.format.nibbles_per_block() - block_samples_encoded + 1 - self.buffer_size).max(1);
 
-            // Encode what is left
-            self.encode_block()?;
+            let mut samples_padded = 0;
+            let blocks_encoded = self.blocks_encoded;
+            while self.blocks_encoded == blocks_encoded {
+                let end = (self.format.nibbles_per_block() + 2 - self.block_samples_encoded.unwrap_or(0)).min(PCM_BUFFER_CAPACITY);
+                self.pad_buffer(end, samples_padded, fade_length, &last_samples);
+                samples_padded += end - self.buffer_size;
+                self.buffer_size = end;
+                self.encode_buffer()?;
+            }
         }
+        else if self.blocks_encoded != 0 {
+            // The last block was already filled by the input.
+            real_samples = self.format.samples_per_block();
+        }
+
+        #[cfg(feature = "std")]
+        self.write_layout_groups(true)?;
+
         self.reset();
-        Ok(())
+        Ok(real_samples)
+    }
+
+    /// Fill the buffer up to `end` according to the tail padding.
+    ///
+    /// `samples_padded` is the number of samples padded before, and `fade_length` is the number of samples until the end
+    /// of the block.
+    fn pad_buffer(&mut self, end: usize, samples_padded: usize, fade_length: usize, last_samples: &[i16; MAX_AUDIO_CHANNEL_COUNT]) {
+        let buffer_size = self.buffer_size;
+        let loop_samples_size = self.loop_samples_size;
+        let fade_length = fade_length as i32;
+
+        for ((c, loop_samples), &last_sample) in self.buffer[0..self.num_channels].iter_mut().zip(self.loop_samples.iter()).zip(last_samples.iter()) {
+            let padding = &mut c[buffer_size..end];
+
+            match self.tail_padding {
+                TailPadding::HoldLastSample => padding.fill(last_sample),
+                TailPadding::FadeOut => {
+                    for (i, b) in padding.iter_mut().enumerate() {
+                        let remaining = (fade_length - 1 - (samples_padded + i) as i32).max(0);
+                        *b = (last_sample as i32 * remaining / fade_length) as i16;
+                    }
+                },
+                TailPadding::LoopStart(_) if loop_samples_size != 0 => {
+                    for (i, b) in padding.iter_mut().enumerate() {
+                        *b = loop_samples[(samples_padded + i) % loop_samples_size];
+                    }
+                },
+                TailPadding::Zero | TailPadding::LoopStart(_) => padding.fill(0)
+            }
+        }
+    }
+
+    /// Copy any of the samples just loaded into the buffer that are at the start of the loop.
+    fn capture_loop_samples(&mut self, loop_start: usize, samples_loaded: usize) {
+        let first = self.samples_received;
+        let start = loop_start.max(first);
+        let end = (loop_start + PCM_BUFFER_CAPACITY).min(first + samples_loaded);
+        if start >= end {
+            return
+        }
+
+        let source_start = self.buffer_size + (start - first);
+        let destination_start = start - loop_start;
+        let count = end - start;
+        for c in 0..self.num_channels {
+            self.loop_samples[c][destination_start..destination_start + count].copy_from_slice(&self.buffer[c][source_start..source_start + count]);
+        }
+        self.loop_samples_size = destination_start + count;
     }
 
     /// Reset the encoder immediately without writing any more samples.
@@ -163,65 +1251,539 @@ impl<'a, E> XboxADPCMEncoder<'a, E> where E: Sized {
     /// Any samples yet to be encoded will be dropped. If this is not desired, call [`XboxADPCMEncoder::finish`] instead.
     pub fn reset(&mut self) {
         self.predictors_initialized = false;
+        self.partial_frame_size = 0;
+        self.partial_byte = None;
+        self.samples_received = 0;
+        self.loop_samples_size = 0;
+        self.blocks_encoded = 0;
+        self.primed_block = None;
         self.buffer_size = 0;
+        self.buffer_position = 0;
+        self.block_samples_encoded = None;
+        self.output_size = 0;
+        self.bytes_written = 0;
+        self.squared_error = 0;
+        self.peak_error = 0;
+        self.cancelled = false;
+        #[cfg(feature = "std")]
+        self.layout_buffer.clear();
+    }
+
+    /// Set how [`XboxADPCMEncoder::finish`] fills the unused samples of the last block.
+    ///
+    /// This is [`TailPadding::Zero`] by default. For [`TailPadding::LoopStart`], this must be set before the sample at
+    /// the loop start is encoded.
+    pub fn set_tail_padding(&mut self, tail_padding: TailPadding) {
+        self.tail_padding = tail_padding;
+    }
+
+    /// Get how [`XboxADPCMEncoder::finish`] fills the unused samples of the last block.
+    pub fn tail_padding(&self) -> TailPadding {
+        self.tail_padding
+    }
+
+    /// Set how sample deltas are calculated, which must match the decoder.
+    ///
+    /// This is [`ADPCMDeltaMode::ShiftAdd`] by default, and it must not be changed while a block is being encoded.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::{ADPCMDeltaMode, XboxADPCMDecoder, XboxADPCMEncoder};
+    ///
+    /// let samples: Vec<i16> = (0..4096).map(|i| ((i as f64 / 20.0).sin() * 8000.0) as i16).collect();
+    /// let mut adpcm = Vec::new();
+    ///
+    /// let mut encoder = XboxADPCMEncoder::new(1, 3, &mut adpcm);
+    /// encoder.set_delta_mode(ADPCMDeltaMode::Multiply);
+    /// encoder.encode(&[&samples]).unwrap();
+    /// encoder.finish().unwrap();
+    ///
+    /// let mut output = [Vec::new()];
+    /// let mut decoder = XboxADPCMDecoder::new(1, &mut output);
+    /// decoder.set_delta_mode(ADPCMDeltaMode::Multiply);
+    /// decoder.decode(&adpcm).unwrap();
+    ///
+    /// assert_eq!(output[0].len(), 4096);
+    /// ```
+    pub fn set_delta_mode(&mut self, delta_mode: ADPCMDeltaMode) {
+        self.delta_mode = delta_mode;
+    }
+
+    /// Get how sample deltas are calculated.
+    pub fn delta_mode(&self) -> ADPCMDeltaMode {
+        self.delta_mode
+    }
+
+    /// Set the cost the encoder minimizes when choosing each 4-bit sample.
+    ///
+    /// This is [`ErrorMetric::Squared`] by default. The statistics given to the observer are always the squared
+    /// error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::{ErrorMetric, XboxADPCMEncoder, compare_adpcm};
+    ///
+    /// let samples: Vec<i16> = (0..4096).map(|i| ((i as f64 / 20.0).sin() * 8000.0) as i16).collect();
+    /// let mut adpcm = Vec::new();
+    ///
+    /// let mut encoder = XboxADPCMEncoder::new(1, 3, &mut adpcm);
+    /// encoder.set_error_metric(ErrorMetric::PERCEPTUAL);
+    /// encoder.encode(&[&samples]).unwrap();
+    /// encoder.finish().unwrap();
+    ///
+    /// assert!(compare_adpcm(&[&samples], &adpcm).min_snr().unwrap() > 20.0);
+    /// ```
+    pub fn set_error_metric(&mut self, error_metric: ErrorMetric) {
+        self.error_metric = error_metric;
+    }
+
+    /// Get the cost the encoder minimizes when choosing each 4-bit sample.
+    pub fn error_metric(&self) -> ErrorMetric {
+        self.error_metric
+    }
+
+    /// Set an observer to notify after each block is encoded, or `None` to remove it.
+    ///
+    /// If the observer returns [`EncodeControl::Cancel`], the encoding function that wrote the block stops without
+    /// encoding the rest of its input, and [`XboxADPCMEncoder::was_cancelled`] returns `true` until the next encoding
+    /// function is called. The encoder is then reset as with [`XboxADPCMEncoder::reset`], so any samples and blocks
+    /// that were not written to the sink yet are dropped. [`XboxADPCMEncoder::encode_looped`] returns `None` instead of
+    /// loop points if it is cancelled. Returning [`EncodeControl::Cancel`] for the last block written by
+    /// [`XboxADPCMEncoder::finish`] has no effect.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::{EncodeControl, EncodeProgress, XboxADPCMEncoder};
+    ///
+    /// let samples = vec![1000i16; 10000];
+    /// let mut output = Vec::new();
+    ///
+    /// // Cancel after the tenth block.
+    /// let mut observer = |progress: &EncodeProgress| {
+    ///     if progress.blocks_encoded == 10 { EncodeControl::Cancel } else { EncodeControl::Continue }
+    /// };
+    ///
+    /// let mut encoder = XboxADPCMEncoder::new(1, 3, &mut output);
+    /// encoder.set_observer(Some(&mut observer));
+    /// encoder.encode(&[&samples]).unwrap();
+    /// assert!(encoder.was_cancelled());
+    /// assert_eq!(encoder.finish().unwrap(), 0);
+    ///
+    /// assert_eq!(output.len(), 36 * 10);
+    /// ```
+    pub fn set_observer(&mut self, observer: Option<&'a mut dyn XboxADPCMEncodeObserver>) {
+        self.observer = observer;
+    }
+
+    /// Get whether the observer cancelled the last call to an encoding function such as [`XboxADPCMEncoder::encode`].
+    ///
+    /// This is not cleared when the encoder is reset or finished, only when another encoding function is called.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::{EncodeControl, EncodeProgress, XboxADPCMEncoder};
+    ///
+    /// let samples: Vec<i16> = (0..1000).map(|i| ((i as f64 / 10.0).sin() * 8000.0) as i16).collect();
+    /// let mut output = Vec::new();
+    /// let mut observer = |progress: &EncodeProgress| {
+    ///     if progress.blocks_encoded == 2 { EncodeControl::Cancel } else { EncodeControl::Continue }
+    /// };
+    ///
+    /// let mut encoder = XboxADPCMEncoder::new(1, 3, &mut output);
+    /// encoder.set_observer(Some(&mut observer));
+    /// assert_eq!(encoder.encode_looped(&[&samples], 100, 900).unwrap(), None);
+    /// assert!(encoder.was_cancelled());
+    ///
+    /// encoder.reset();
+    /// assert!(encoder.was_cancelled());
+    ///
+    /// encoder.encode(&[&samples[..10]]).unwrap();
+    /// assert!(!encoder.was_cancelled());
+    /// ```
+    pub fn was_cancelled(&self) -> bool {
+        self.last_call_cancelled
+    }
+
+    /// Set whether each block is decoded as it is written and compared with the samples and step indices the encoder
+    /// reconstructed while encoding it.
+    ///
+    /// If they differ, the encoding function that wrote the block returns a [`VerifyError`] with the block, channel
+    /// and sample that did not match, converted to the error type of the sink. The encoder should be reset before it
+    /// is used again. This is disabled by default, and it should only be changed between blocks.
+    ///
+    /// The header and each chunk of 4-bit samples are decoded from the encoder's output buffer as they are written
+    /// there, which are the bytes later passed to the sink. With a block layout other than
+    /// [`ADPCMBlockLayout::Interleaved`], this happens before the blocks are rearranged into the layout, so the
+    /// rearranging is not checked.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::{VerifyError, XboxADPCMEncodeSink, XboxADPCMEncoder};
+    ///
+    /// struct VerifiedOutput(Vec<u8>);
+    ///
+    /// impl XboxADPCMEncodeSink for VerifiedOutput {
+    ///     type Error = VerifyError;
+    ///
+    ///     fn write(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+    ///         self.0.extend_from_slice(bytes);
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let samples: Vec<i16> = (0..1000).map(|i| ((i as f64 / 10.0).sin() * 8000.0) as i16).collect();
+    /// let mut output = VerifiedOutput(Vec::new());
+    ///
+    /// let mut encoder = XboxADPCMEncoder::new(1, 3, &mut output);
+    /// encoder.set_verify(true);
+    /// encoder.encode(&[&samples]).unwrap();
+    /// encoder.finish().unwrap();
+    ///
+    /// assert_eq!(output.0.len(), 36 * 16);
+    /// ```
+    pub fn set_verify(&mut self, verify: bool) where E: From<VerifyError> {
+        self.verify = if verify { Some(E::from) } else { None };
     }
 
-    /// Encode the contents of the buffer.
-    fn encode_block(&mut self) -> Result<(), E> {
-        debug_assert_eq!(PCM_BUFFER_CAPACITY, self.buffer_size, "called encode_block on a non-populated sample buffer");
-        debug_assert!(self.predictors_initialized, "called encode_block but predictors not initialized");
+    /// Get whether each block is verified as it is written.
+    pub fn verify(&self) -> bool {
+        self.verify.is_some()
+    }
 
-        let mut bytes_to_write = [0u8; ADPCM_BLOCK_SIZE * MAX_AUDIO_CHANNEL_COUNT];
-        let total_bytes_to_write = ADPCM_BLOCK_SIZE * self.num_channels;
+    /// Set the block layout.
+    ///
+    /// Blocks are held back until a whole group of blocks is encoded, which for [`ADPCMBlockLayout::Planar`] is the
+    /// whole stream, so this must not be changed while encoding.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the layout is [`ADPCMBlockLayout::Stride`] with a stride of 0.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::{ADPCMBlockLayout, XboxADPCMDecoder, XboxADPCMEncoder};
+    ///
+    /// let samples: Vec<i16> = (0..1000).map(|i| ((i as f64 / 10.0).sin() * 8000.0) as i16).collect();
+    /// let mut adpcm = Vec::new();
+    ///
+    /// let mut encoder = XboxADPCMEncoder::new(2, 3, &mut adpcm);
+    /// encoder.set_layout(ADPCMBlockLayout::Stride(4));
+    /// encoder.encode(&[&samples, &samples]).unwrap();
+    /// encoder.finish().unwrap();
+    ///
+    /// let mut output = [Vec::new(), Vec::new()];
+    /// let mut decoder = XboxADPCMDecoder::new(2, &mut output);
+    /// decoder.set_layout(ADPCMBlockLayout::Stride(4));
+    /// decoder.decode(&adpcm).unwrap();
+    ///
+    /// assert_eq!(output[0], output[1]);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn set_layout(&mut self, layout: ADPCMBlockLayout) {
+        assert_ne!(layout, ADPCMBlockLayout::Stride(0), "stride must not be 0");
+        self.layout = layout;
+    }
+
+    /// Get the block layout.
+    #[cfg(feature = "std")]
+    pub fn layout(&self) -> ADPCMBlockLayout {
+        self.layout
+    }
+
+    /// Get the block format.
+    pub fn format(&self) -> ADPCMBlockFormat {
+        self.format
+    }
+
+    /// Get the number of channels.
+    pub fn num_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    /// Encode as many samples from the buffer as possible without needing more samples.
+    fn encode_buffer(&mut self) -> Result<(), E> {
+        let nibbles_per_block = self.format.nibbles_per_block();
+
+        loop {
+            // We need the last sample encoded, the next chunk, and the samples after it the lookahead can see.
+            let block_samples_encoded = self.block_samples_encoded.unwrap_or(0);
+            let samples_needed = (1 + SAMPLES_PER_CHUNK + self.lookahead).min(nibbles_per_block + 2 - block_samples_encoded).min(PCM_BUFFER_CAPACITY);
+            if self.buffer_size < samples_needed {
+                return Ok(())
+            }
+
+            if self.block_samples_encoded.is_none() {
+                if !self.predictors_initialized {
+                    if self.buffer_size < (nibbles_per_block + 2).min(PCM_BUFFER_CAPACITY) {
+                        return Ok(())
+                    }
+                    self.initialize_predictors();
+                }
+                self.write_block_header()?;
+            }
+
+            self.encode_chunk()?;
+            if self.cancelled {
+                return Ok(())
+            }
+        }
+    }
+
+    /// Write the header of a block using the first sample in the buffer.
+    fn write_block_header(&mut self) -> Result<(), E> {
+        debug_assert!(self.predictors_initialized, "called write_block_header but predictors not initialized");
+
+        let header_size = 4 * self.num_channels;
+        self.make_room_for_output(header_size)?;
+
+        // Use a different header if this block is primed
+        if let Some(primed) = self.primed_block.filter(|p| p.block == self.blocks_encoded) {
+            for ch in 0..self.num_channels {
+                self.buffer[ch][0] = primed.samples[ch];
+                self.channels[ch].index = primed.indices[ch];
+            }
+        }
+
+        let collect_info = self.observer.is_some();
+        if collect_info {
+            self.block_info = EncodedBlockInfo { block: self.blocks_encoded, num_channels: self.num_channels, ..EncodedBlockInfo::default() };
+        }
 
-        // Write the header
         for ch in 0..self.num_channels {
             // Get our first sample and set it since it's uncompressed.
-            let s = self.buffer[ch][0];
-            bytes_to_write[0 + ch * 4] = (s & 0xFF) as u8; // write the first sample uncompressed
-            bytes_to_write[1 + ch * 4] = ((s >> 8) & 0xFF) as u8;
-            bytes_to_write[2 + ch * 4] = self.channels[ch].index as u8;
-            self.channels[ch].pcmdata = s as i32;
+            self.channels[ch].pcmdata = self.buffer[ch][0] as i32;
+            let header = &mut self.output[self.output_size + ch * 4..];
+            write_header(&self.channels[ch], header);
+
+            self.verify_channels[ch] = read_header(header);
+            self.verify_sample(ch, 0, self.channels[ch])?;
+            self.error_history[ch] = [0, 0];
+
+            if collect_info {
+                let info = &mut self.block_info.channels[ch];
+                info.header_sample = self.buffer[ch][0];
+                info.step_index = self.channels[ch].index as u8;
+            }
         }
 
-        // Write the chunks
-        self.encode_chunks(&mut bytes_to_write[self.num_channels * 4..total_bytes_to_write]);
-        for ch in 0..self.num_channels {
-            for b in 0..PCM_BUFFER_EXTRA {
-                self.buffer[ch][b] = self.buffer[ch][PCM_BUFFER_CAPACITY - PCM_BUFFER_EXTRA + b]; // copy the last samples back to the beginning
+        if self.format.outputs_header_sample() && self.index_probe == Some(self.buffer_position) {
+            for (p, c) in self.probed_indices.iter_mut().zip(self.channels.iter()) {
+                *p = c.index;
+            }
+        }
+
+        self.output_size += header_size;
+        self.block_samples_encoded = Some(0);
+        Ok(())
+    }
+
+    /// Encode the next chunk of each channel.
+    fn encode_chunk(&mut self) -> Result<(), E> {
+        const BYTES_PER_CHANNEL_PER_CHUNK: usize = SAMPLES_PER_CHUNK / 2;
+        let chunk_size = BYTES_PER_CHANNEL_PER_CHUNK * self.num_channels;
+        self.make_room_for_output(chunk_size)?;
+
+        // Lookahead cannot see past the first sample of the next block.
+        let nibbles_per_block = self.format.nibbles_per_block();
+        let block_samples_encoded = self.block_samples_encoded.expect("called encode_chunk before writing the header");
+        let visible_end = self.buffer_size.min(nibbles_per_block + 2 - block_samples_encoded);
+        let search = NibbleSearch { delta_mode: self.delta_mode, metric: self.error_metric };
+        let collect_info = self.observer.is_some();
+
+        for channel in 0..self.num_channels {
+            let output_offset = self.output_size + channel * BYTES_PER_CHANNEL_PER_CHUNK;
+            let chunk_samples = &self.buffer[channel][1..visible_end];
+            let mut reconstructed = [ADPCMChannel::default(); SAMPLES_PER_CHUNK];
+            for i in 0..BYTES_PER_CHANNEL_PER_CHUNK {
+                let pchan = &mut self.channels[channel];
+                let history = &mut self.error_history[channel];
+                let buff_offset = i * 2;
+                let low = encode_sample(pchan, history, self.lookahead, &chunk_samples[buff_offset..], search);
+                let low_error = pchan.pcmdata.abs_diff(chunk_samples[buff_offset] as i32);
+                reconstructed[buff_offset] = *pchan;
+                if self.index_probe == Some(self.buffer_position + 1 + buff_offset) {
+                    self.probed_indices[channel] = pchan.index;
+                }
+                let high = encode_sample(pchan, history, self.lookahead, &chunk_samples[buff_offset + 1..], search);
+                let high_error = pchan.pcmdata.abs_diff(chunk_samples[buff_offset + 1] as i32);
+                reconstructed[buff_offset + 1] = *pchan;
+                if self.index_probe == Some(self.buffer_position + 2 + buff_offset) {
+                    self.probed_indices[channel] = pchan.index;
+                }
+                let squared_error = (low_error as u64).pow(2) + (high_error as u64).pow(2);
+                let peak_error = low_error.max(high_error);
+                self.squared_error += squared_error;
+                self.peak_error = self.peak_error.max(peak_error);
+
+                if collect_info {
+                    let info = &mut self.block_info.channels[channel];
+                    info.nibble_histogram[low as usize] += 1;
+                    info.nibble_histogram[high as usize] += 1;
+                    info.squared_error += squared_error;
+                    info.peak_error = info.peak_error.max(peak_error);
+                }
+                self.output[output_offset + i] = low | (high << 4);
+            }
+
+            if self.verify.is_none() {
+                self.verify_channels[channel] = self.channels[channel];
+                continue
+            }
+
+            let word = u32::from_le_bytes(self.output[output_offset..output_offset + BYTES_PER_CHANNEL_PER_CHUNK].try_into().unwrap());
+            for (i, &expected) in reconstructed.iter().enumerate() {
+                decode_nibble(&mut self.verify_channels[channel], (word >> (i * 4)) as u8 & 0xF, self.delta_mode);
+                self.verify_sample(channel, block_samples_encoded + 1 + i, expected)?;
+            }
+        }
+        self.output_size += chunk_size;
+        self.consume_samples(SAMPLES_PER_CHUNK);
+
+        let block_samples_encoded = block_samples_encoded + SAMPLES_PER_CHUNK;
+        if block_samples_encoded < nibbles_per_block {
+            self.block_samples_encoded = Some(block_samples_encoded);
+            return Ok(())
+        }
+
+        // If the header sample is output, the next block starts with a new sample instead of the last one encoded.
+        if self.format.outputs_header_sample() {
+            self.consume_samples(1);
+        }
+        self.block_samples_encoded = None;
+        self.blocks_encoded += 1;
+        self.flush_output()?;
+
+        if let Some(ref mut observer) = self.observer {
+            observer.block_info(&self.block_info);
+
+            let progress = EncodeProgress {
+                samples_received: self.samples_received,
+                samples_encoded: self.blocks_encoded * self.format.samples_per_block(),
+                blocks_encoded: self.blocks_encoded,
+                squared_error: self.squared_error,
+                peak_error: self.peak_error
+            };
+            if observer.block_encoded(&progress) == EncodeControl::Cancel {
+                self.cancelled = true;
             }
         }
-        self.buffer_size = PCM_BUFFER_EXTRA;
-
-        // Write all of it
-        self.sink.write(&bytes_to_write[..total_bytes_to_write])
-    }
-
-    /// Encode all chunks
-    fn encode_chunks(&mut self, output: &mut [u8]) {
-        const BYTES_PER_CHANNEL_PER_BLOCK: usize = SAMPLES_PER_CHUNK / 2;
-        let output_channel_stride = self.num_channels * BYTES_PER_CHANNEL_PER_BLOCK;
-
-        for chunk in 0..CHUNKS_PER_BLOCK {
-            let chunk_start = 1 + chunk * SAMPLES_PER_CHUNK;
-            let output_offset = output_channel_stride * chunk;
-            for channel in 0..self.num_channels {
-                let output_offset = output_offset + channel * BYTES_PER_CHANNEL_PER_BLOCK;
-                let chunk_samples = &self.buffer[channel][chunk_start..];
-                for i in 0..BYTES_PER_CHANNEL_PER_BLOCK {
-                    let pchan = &mut self.channels[channel];
-                    let buff_offset = i * 2;
-                    let low = encode_sample(pchan, self.lookahead, &chunk_samples[buff_offset..]);
-                    let high = encode_sample(pchan, self.lookahead, &chunk_samples[buff_offset + 1..]);
-                    output[output_offset + i] = low | (high << 4);
+
+        Ok(())
+    }
+
+    /// Compare the decoder state of a channel with what the encoder reconstructed if verification is enabled.
+    fn verify_sample(&self, channel: usize, sample: usize, expected: ADPCMChannel) -> Result<(), E> {
+        let Some(verify) = self.verify else {
+            return Ok(())
+        };
+
+        let decoded = self.verify_channels[channel];
+        if decoded == expected {
+            return Ok(())
+        }
+
+        Err(verify(VerifyError {
+            block: self.blocks_encoded,
+            channel,
+            sample,
+            expected_sample: expected.pcmdata as i16,
+            decoded_sample: decoded.pcmdata as i16,
+            expected_step_index: expected.index as u8,
+            decoded_step_index: decoded.index as u8
+        }))
+    }
+
+    /// Reset the encoder if the observer cancelled encoding, returning the result of the call.
+    fn reset_if_cancelled(&mut self, result: Result<(), E>) -> Result<(), E> {
+        self.last_call_cancelled = self.cancelled;
+        if self.cancelled {
+            self.reset();
+        }
+        result
+    }
+
+    /// Remove samples from the start of the buffer.
+    fn consume_samples(&mut self, sample_count: usize) {
+        for c in &mut self.buffer[..self.num_channels] {
+            c.copy_within(sample_count..self.buffer_size, 0);
+        }
+        self.buffer_size -= sample_count;
+        self.buffer_position += sample_count;
+    }
+
+    /// Write the output if there is no room for the given number of bytes.
+    fn make_room_for_output(&mut self, bytes_amount: usize) -> Result<(), E> {
+        if self.output_size + bytes_amount > self.output.len() {
+            self.flush_output()?;
+        }
+        Ok(())
+    }
+
+    /// Write all of the output.
+    fn flush_output(&mut self) -> Result<(), E> {
+        let output_size = self.output_size;
+        self.output_size = 0;
+
+        #[cfg(feature = "std")]
+        if self.layout != ADPCMBlockLayout::Interleaved {
+            self.layout_buffer.extend_from_slice(&self.output[..output_size]);
+            return self.write_layout_groups(false)
+        }
+
+        self.bytes_written += output_size;
+        self.sink.write(&self.output[..output_size])
+    }
+
+    /// Write whole groups of blocks in the layout buffer as mono blocks, including a shorter group at the end if
+    /// finishing.
+    #[cfg(feature = "std")]
+    fn write_layout_groups(&mut self, finishing: bool) -> Result<(), E> {
+        let channel_block_size = self.format.channel_block_size();
+        let block_size = self.format.block_size(self.num_channels);
+        let blocks_buffered = self.layout_buffer.len() / block_size;
+        let group_blocks = match self.layout {
+            ADPCMBlockLayout::Interleaved => return Ok(()),
+            ADPCMBlockLayout::Planar if finishing => blocks_buffered,
+            ADPCMBlockLayout::Planar => return Ok(()),
+            ADPCMBlockLayout::Stride(n) => n
+        };
+
+        let mut blocks_written = 0;
+        let mut group = std::vec::Vec::new();
+        while blocks_written < blocks_buffered && (finishing || blocks_buffered - blocks_written >= group_blocks) {
+            let blocks = group_blocks.min(blocks_buffered - blocks_written);
+            let input = &self.layout_buffer[blocks_written * block_size..(blocks_written + blocks) * block_size];
+
+            group.clear();
+            group.resize(input.len(), 0);
+            for block in 0..blocks {
+                for word in 0..channel_block_size / 4 {
+                    for c in 0..self.num_channels {
+                        let from = block * block_size + (word * self.num_channels + c) * 4;
+                        let to = (c * blocks + block) * channel_block_size + word * 4;
+                        group[to..to + 4].copy_from_slice(&input[from..from + 4]);
+                    }
                 }
             }
+
+            self.bytes_written += group.len();
+            self.sink.write(&group)?;
+            blocks_written += blocks;
         }
+
+        self.layout_buffer.drain(..blocks_written * block_size);
+        Ok(())
     }
 
     /// Initialize predictors with the contents of the buffer.
     ///
-    /// This should be called whenever a block is encoded.
+    /// This should be called before the first block is encoded.
     fn initialize_predictors(&mut self) {
         if self.predictors_initialized {
             return
@@ -257,16 +1819,18 @@ impl<'a, E> XboxADPCMEncoder<'a, E> where E: Sized {
     }
 }
 
-/// Calculate minimum error recursively.
-fn calculate_minimum_error(index: usize, pcmdata: i32, sample: i32, samples: &[i16], lookahead: usize, best_nibble: &mut u8) -> f64 {
+/// Calculate the minimum cost of the first sample and the samples lookahead can see recursively.
+fn calculate_minimum_error(index: usize, pcmdata: i32, history: [i32; 2], samples: &[i16], lookahead: usize, search: NibbleSearch, best_nibble: &mut u8) -> f64 {
+    let sample = samples[0] as i32;
     let calculate_minimum_error_next = |index: usize, pcmdata: i32, nibble: u8| -> f64 {
         let index = clamp_table_index(index as isize + INDEX_TABLE[nibble as usize & 0x7]);
-        calculate_minimum_error(index, pcmdata, samples[0] as i32, &samples[1..], lookahead - 1, &mut 0)
+        let history = [pcmdata - sample, history[0]];
+        calculate_minimum_error(index, pcmdata, history, &samples[1..], lookahead - 1, search, &mut 0)
     };
 
     // Get our delta!
     let delta = sample - pcmdata;
-    let step = STEP_TABLE[index] as u16;
+    let step = STEP_TABLE[index];
 
     // Encode our nibble
     let nibble = if delta < 0 {
@@ -278,8 +1842,8 @@ fn calculate_minimum_error(index: usize, pcmdata: i32, sample: i32, samples: &[i
     *best_nibble = nibble;
 
     // Calculate the minimum error. Return if base case.
-    let pcmdata_a = clamp_sample(pcmdata + calculate_delta(step, nibble));
-    let mut min_error = pcmdata_a.abs_diff(sample).pow(2) as f64;
+    let pcmdata_a = clamp_sample(pcmdata + calculate_delta(step, nibble, search.delta_mode));
+    let mut min_error = search.metric.cost(pcmdata_a - sample, history);
     if lookahead == 0 {
         return min_error;
     }
@@ -291,8 +1855,8 @@ fn calculate_minimum_error(index: usize, pcmdata: i32, sample: i32, samples: &[i
             continue
         }
 
-        let pcmdata_b = clamp_sample(pcmdata + calculate_delta(step, nibble2));
-        let error = pcmdata_b.abs_diff(sample).pow(2) as f64;
+        let pcmdata_b = clamp_sample(pcmdata + calculate_delta(step, nibble2, search.delta_mode));
+        let error = search.metric.cost(pcmdata_b - sample, history);
 
         // If the error is already too high, skip so we don't do any (possibly) slow recursion
         if error >= min_error {
@@ -311,16 +1875,16 @@ fn calculate_minimum_error(index: usize, pcmdata: i32, sample: i32, samples: &[i
     min_error
 }
 
-/// Encode the samples.
-fn encode_sample(pchan: &mut ADPCMChannel, lookahead: usize, samples: &[i16]) -> u8 {
+/// Encode the samples, updating the errors of the last two samples.
+pub(crate) fn encode_sample(pchan: &mut ADPCMChannel, history: &mut [i32; 2], lookahead: usize, samples: &[i16], search: NibbleSearch) -> u8 {
     let current_sample = samples[0] as i32;
-    let next_samples = &samples[1..];
     let step = STEP_TABLE[pchan.index];
 
     let mut nibble = 0;
-    calculate_minimum_error(pchan.index, pchan.pcmdata, current_sample, next_samples, lookahead.min(next_samples.len()), &mut nibble);
-    pchan.index = clamp_table_index(pchan.index as isize + INDEX_TABLE[(nibble & 0x7) as usize]) as usize;
-    pchan.pcmdata = clamp_sample(pchan.pcmdata + calculate_delta(step, nibble));
+    calculate_minimum_error(pchan.index, pchan.pcmdata, *history, samples, lookahead.min(samples.len() - 1), search, &mut nibble);
+    pchan.index = clamp_table_index(pchan.index as isize + INDEX_TABLE[(nibble & 0x7) as usize]);
+    pchan.pcmdata = clamp_sample(pchan.pcmdata + calculate_delta(step, nibble, search.delta_mode));
+    *history = [pchan.pcmdata - current_sample, history[0]];
 
     nibble
 }
@@ -0,0 +1,277 @@
+use super::*;
+
+/// Smallest size of one channel's part of a block, which is a header and one 4-byte word of samples.
+const MIN_CHANNEL_BLOCK_SIZE: usize = 8;
+
+/// Block layout of an IMA ADPCM stream.
+///
+/// Every block starts with a 4-byte header for each channel, holding an uncompressed sample and the step index. The
+/// rest of the block is made of 4-byte words of eight 4-bit samples, with the words of each channel interleaved, which
+/// is the layout used by Microsoft IMA ADPCM (`WAVE_FORMAT_IMA_ADPCM`). Xbox ADPCM is this layout with a fixed size of
+/// 36 bytes per channel.
+///
+/// How samples are framed into blocks is set by [`ADPCMFraming`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct ADPCMBlockFormat {
+    /// Size of one channel's part of a block in bytes, including the header
+    channel_block_size: usize,
+
+    /// How samples are framed into blocks
+    framing: ADPCMFraming
+}
+
+/// How samples are framed into blocks.
+///
+/// Each channel of a block has one uncompressed sample in its header followed by its 4-bit samples, which for Xbox
+/// ADPCM is 1 + 64 samples. Implementations differ in whether the header sample is output, which changes both the
+/// decoded length and where each block starts in the decoded stream.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum ADPCMFraming {
+    /// The header repeats the last sample of the previous block and is not output, so each block decodes to only its
+    /// 4-bit samples and the first sample of the stream is only stored in the header of the first block.
+    ///
+    /// This is what the Xbox and this crate use by default, with 36-byte blocks decoding to 64 samples per channel.
+    #[default]
+    Overlapped,
+
+    /// The header sample is output as the first sample of the block, followed by its 4-bit samples.
+    ///
+    /// This is what Microsoft IMA ADPCM (`WAVE_FORMAT_IMA_ADPCM`) decoders do, so an Xbox ADPCM stream decoded as IMA
+    /// ADPCM is framed this way, with 36-byte blocks decoding to 65 samples per channel.
+    HeaderSample
+}
+
+/// How the difference each 4-bit sample makes is calculated from the step size.
+///
+/// A 4-bit sample is a sign bit and a 3-bit magnitude `n`, and adds or subtracts roughly `(2n + 1) * step / 8`.
+/// Encoders and decoders have to agree on how this is rounded, or the decoded samples drift from what the encoder
+/// expected.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum ADPCMDeltaMode {
+    /// Add `step >> 3` plus `step`, `step >> 1` and `step >> 2` for each set bit of `n`, which is what the IMA ADPCM
+    /// reference and the Xbox use.
+    #[default]
+    ShiftAdd,
+
+    /// Calculate `((2n + 1) * step) >> 3`, which is more precise and is used by some other IMA ADPCM implementations.
+    Multiply
+}
+
+/// How the blocks of each channel are arranged in a stream.
+///
+/// This crate treats a block as one header and the samples for every channel, as described in [`ADPCMBlockFormat`].
+/// Other layouts store each channel as its own mono blocks instead, with one channel's part of each block stored as a
+/// mono block of [`ADPCMBlockFormat::channel_block_size`] bytes.
+///
+/// [`XboxADPCMDecoder`] and [`XboxADPCMEncoder`] have to hold back blocks to read or write other layouts, so they only
+/// support them if the `"std"` feature is enabled.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum ADPCMBlockLayout {
+    /// Each block holds every channel, with the words of each channel interleaved.
+    #[default]
+    Interleaved,
+
+    /// All blocks of the first channel, then all blocks of the second channel, and so on.
+    Planar,
+
+    /// The given number of blocks of the first channel, then the same number of blocks of the second channel, and so on,
+    /// repeating until the end of the stream.
+    ///
+    /// The last group may be shorter, with the same number of blocks for each channel. For containers that set the
+    /// interleave in bytes, divide it by the channel block size to get the number of blocks.
+    Stride(usize)
+}
+
+impl ADPCMBlockLayout {
+    /// Get the size in bytes of one group of mono blocks for all channels, or `None` if the layout is interleaved or all
+    /// blocks are in one group.
+    #[cfg(feature = "std")]
+    pub(crate) fn group_size(&self, format: &ADPCMBlockFormat, num_channels: usize) -> Option<usize> {
+        match *self {
+            ADPCMBlockLayout::Stride(n) => Some(n * format.block_size(num_channels)),
+            ADPCMBlockLayout::Interleaved | ADPCMBlockLayout::Planar => None
+        }
+    }
+}
+
+impl ADPCMBlockFormat {
+    /// Xbox ADPCM blocks of 36 bytes per channel, decoding to 64 samples per channel.
+    pub const XBOX: ADPCMBlockFormat = ADPCMBlockFormat {
+        channel_block_size: ADPCM_BLOCK_SIZE,
+        framing: ADPCMFraming::Overlapped
+    };
+
+    /// Get the format for standard IMA ADPCM with the given block size for all channels, as stored in `nBlockAlign`.
+    ///
+    /// The header sample is output as the first sample of each block ([`ADPCMFraming::HeaderSample`]).
+    ///
+    /// Returns `None` if `block_align` cannot hold a header and at least one word of samples for each channel, or if
+    /// it is not a multiple of 4 bytes per channel.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::ADPCMBlockFormat;
+    ///
+    /// let format = ADPCMBlockFormat::ima(2048, 2).unwrap();
+    /// assert_eq!(format.samples_per_block(), 2041);
+    ///
+    /// assert!(ADPCMBlockFormat::ima(2050, 2).is_none());
+    /// ```
+    pub fn ima(block_align: usize, num_channels: usize) -> Option<ADPCMBlockFormat> {
+        if num_channels == 0 || block_align % (num_channels * 4) != 0 {
+            return None
+        }
+
+        let channel_block_size = block_align / num_channels;
+        if channel_block_size < MIN_CHANNEL_BLOCK_SIZE {
+            return None
+        }
+
+        Some(ADPCMBlockFormat { channel_block_size, framing: ADPCMFraming::HeaderSample })
+    }
+
+    /// Check that a block has room for a header and whole words of samples.
+    pub(crate) fn is_valid(&self) -> bool {
+        self.channel_block_size >= MIN_CHANNEL_BLOCK_SIZE && self.channel_block_size % 4 == 0
+    }
+
+    /// Get a copy of this format with the given framing.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::{ADPCMBlockFormat, ADPCMFraming};
+    ///
+    /// let format = ADPCMBlockFormat::XBOX.with_framing(ADPCMFraming::HeaderSample);
+    /// assert_eq!(format.samples_per_block(), 65);
+    /// assert_eq!(format.decoded_length(1000), 1000);
+    /// assert_eq!(ADPCMBlockFormat::XBOX.decoded_length(1000), 999);
+    /// ```
+    pub const fn with_framing(self, framing: ADPCMFraming) -> ADPCMBlockFormat {
+        ADPCMBlockFormat { channel_block_size: self.channel_block_size, framing }
+    }
+
+    /// Get the framing.
+    pub fn framing(&self) -> ADPCMFraming {
+        self.framing
+    }
+
+    /// Get the size of one channel's part of a block in bytes, including the header.
+    pub fn channel_block_size(&self) -> usize {
+        self.channel_block_size
+    }
+
+    /// Get the size of a block in bytes for the given number of channels.
+    pub fn block_size(&self, num_channels: usize) -> usize {
+        self.channel_block_size * num_channels
+    }
+
+    /// Get the number of 4-bit samples per channel in a block.
+    pub fn nibbles_per_block(&self) -> usize {
+        (self.channel_block_size - 4) * 2
+    }
+
+    /// Get the number of samples per channel a block decodes to.
+    pub fn samples_per_block(&self) -> usize {
+        self.nibbles_per_block() + self.outputs_header_sample() as usize
+    }
+
+    /// Get whether the header sample is output as the first sample of each block.
+    pub fn outputs_header_sample(&self) -> bool {
+        self.framing == ADPCMFraming::HeaderSample
+    }
+
+    /// Get the number of samples per channel that encoding the given number of samples per channel decodes to, not
+    /// counting padding.
+    ///
+    /// Unless the header sample is output, the first sample is only stored in the header of the first block, so this is
+    /// one less than `sample_count`.
+    pub fn decoded_length(&self, sample_count: usize) -> usize {
+        if self.outputs_header_sample() {
+            sample_count
+        }
+        else {
+            sample_count.saturating_sub(1)
+        }
+    }
+
+    /// Get the number of blocks [`XboxADPCMEncoder`] writes for the given number of samples per channel, including the
+    /// last block padded by [`XboxADPCMEncoder::finish`].
+    pub fn encoded_blocks(&self, sample_count: usize) -> usize {
+        if sample_count == 0 {
+            0
+        }
+        else {
+            // Even a single sample needs a block for its header.
+            self.decoded_length(sample_count).div_ceil(self.samples_per_block()).max(1)
+        }
+    }
+
+    /// Get the number of bytes [`XboxADPCMEncoder`] writes for the given number of samples per channel.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::ADPCMBlockFormat;
+    ///
+    /// let format = ADPCMBlockFormat::XBOX;
+    ///
+    /// // The first sample is only stored in the header, so 65 samples fit in one block.
+    /// assert_eq!(format.encoded_size(65, 2), 72);
+    /// assert_eq!(format.encoded_size(66, 2), 144);
+    /// assert_eq!(format.padding_samples(66), 63);
+    /// ```
+    pub fn encoded_size(&self, sample_count: usize, num_channels: usize) -> usize {
+        self.encoded_blocks(sample_count) * self.block_size(num_channels)
+    }
+
+    /// Get the number of samples per channel [`XboxADPCMEncoder::finish`] pads the last block with when encoding the given
+    /// number of samples per channel.
+    pub fn padding_samples(&self, sample_count: usize) -> usize {
+        self.encoded_blocks(sample_count) * self.samples_per_block() - self.decoded_length(sample_count)
+    }
+
+    /// Get the number of samples per channel [`XboxADPCMDecoder`] outputs for the given number of bytes, including the
+    /// samples of an incomplete block at the end that are written by [`XboxADPCMDecoder::finish`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::ADPCMBlockFormat;
+    ///
+    /// let format = ADPCMBlockFormat::ima(1024, 2).unwrap();
+    ///
+    /// // Two whole blocks, then a header and two words of the third block
+    /// assert_eq!(format.decoded_samples(1024 * 2 + 8 * 3, 2), 1017 * 2 + 1 + 8 * 2);
+    /// ```
+    pub fn decoded_samples(&self, byte_count: usize, num_channels: usize) -> usize {
+        let block_size = self.block_size(num_channels);
+        let whole_blocks = byte_count / block_size;
+
+        // Incomplete blocks are decoded one word per channel at a time, starting with the header.
+        let words = (byte_count % block_size) / (4 * num_channels);
+        let partial_samples = match words {
+            0 => 0,
+            w => (w - 1) * SAMPLES_PER_CHUNK + self.outputs_header_sample() as usize
+        };
+
+        whole_blocks * self.samples_per_block() + partial_samples
+    }
+
+    /// Get the duration in seconds of the given number of bytes at the given sample rate, counted the same as
+    /// [`ADPCMBlockFormat::decoded_samples`].
+    pub fn duration(&self, byte_count: usize, num_channels: usize, sample_rate: u32) -> f64 {
+        self.decoded_samples(byte_count, num_channels) as f64 / sample_rate as f64
+    }
+}
+
+impl Default for ADPCMBlockFormat {
+    fn default() -> Self {
+        ADPCMBlockFormat::XBOX
+    }
+}
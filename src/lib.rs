@@ -51,17 +51,83 @@
 //! # fn read_some_pcm_samples() -> (Vec<i16>, Vec<i16>) {
 //! #    return (vec![0i16; 5], vec![0i16; 5])
 //! # }
+//! ```
+//!
+//! # Block framing
+//!
+//! Each 36-byte Xbox ADPCM block holds, for each channel, a header with one uncompressed sample followed by 64 4-bit
+//! samples. By default, this crate uses the framing the Xbox uses, where the header repeats the last sample of the
+//! previous block and is not output, so every block decodes to 64 samples per channel and the first sample of a stream
+//! is only stored in the header of the first block. Many other tools decode Xbox ADPCM as standard IMA ADPCM instead,
+//! outputting the header sample too for 65 samples per block.
+//!
+//! Use [`ADPCMBlockFormat::with_framing`] with [`XboxADPCMEncoder::with_format`] or [`XboxADPCMDecoder::with_format`] to
+//! match them.
+//!
+//! ```
+//! use xbadpcm::{ADPCMBlockFormat, ADPCMFraming, XboxADPCMDecoder};
+//!
+//! let adpcm_data = vec![0u8; 36 * 2];
+//! let mut output = [Vec::new()];
+//!
+//! let format = ADPCMBlockFormat::XBOX.with_framing(ADPCMFraming::HeaderSample);
+//! let mut decoder = XboxADPCMDecoder::with_format(1, format, &mut output);
+//! decoder.decode(&adpcm_data).unwrap();
+//! decoder.finish().unwrap();
+//!
+//! assert_eq!(output[0].len(), 65 * 2);
+//! ```
 
 #![no_std]
 
 #[cfg(feature = "std")]
 extern crate std;
 
+#[cfg(feature = "serde")]
+extern crate serde;
+
 mod util;
 use util::*;
 
+mod format;
+pub use format::*;
+
+mod block;
+pub use block::*;
+
 mod encoder;
 pub use encoder::*;
 
 mod decoder;
 pub use decoder::*;
+
+mod codec;
+pub use codec::*;
+
+mod msadpcm;
+pub use msadpcm::*;
+
+mod transcode;
+pub use transcode::*;
+
+mod convert;
+pub use convert::*;
+
+mod looping;
+pub use looping::*;
+
+mod wav;
+pub use wav::*;
+
+mod xwb;
+pub use xwb::*;
+
+#[cfg(feature = "std")]
+mod xwb_writer;
+#[cfg(feature = "std")]
+pub use xwb_writer::*;
+
+#[cfg(feature = "std")]
+mod analysis;
+#[cfg(feature = "std")]
+pub use analysis::*;
@@ -0,0 +1,146 @@
+use core::ops::Deref;
+
+use super::*;
+
+/// One sample for each channel of a stream.
+///
+/// This dereferences to a slice with one sample per channel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PCMFrame {
+    /// Samples
+    samples: [i16; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Number of channels
+    num_channels: usize
+}
+
+impl Deref for PCMFrame {
+    type Target = [i16];
+
+    fn deref(&self) -> &[i16] {
+        &self.samples[..self.num_channels]
+    }
+}
+
+/// Iterator that decodes Xbox ADPCM blocks and loops between two points.
+///
+/// Playback starts at the first sample. Whenever the loop end is reached, playback jumps back to the loop start, which
+/// may be anywhere in a block. After jumping back the given number of times, playback continues past the loop end to the
+/// end of the data.
+///
+/// Loop points are counted in samples per channel as they are output by [`XboxADPCMDecoder`], such as the ones returned
+/// by [`XboxADPCMEncoder::encode_looped`].
+///
+/// # Example
+///
+/// ```
+/// use xbadpcm::XboxADPCMLoopingDecoder;
+///
+/// let adpcm_data = vec![0u8; 36 * 4];
+///
+/// // Play the first 100 samples, then loop from 80 to 100 twice, then play the rest.
+/// let decoder = XboxADPCMLoopingDecoder::new(&adpcm_data, 1, 80, 100, Some(2));
+/// assert_eq!(decoder.count(), 64 * 4 + 20 * 2);
+/// ```
+pub struct XboxADPCMLoopingDecoder<'a> {
+    /// ADPCM data
+    data: &'a [u8],
+
+    /// Number of channels
+    num_channels: usize,
+
+    /// First sample of the loop
+    loop_start: usize,
+
+    /// Sample after the last sample of the loop
+    loop_end: usize,
+
+    /// Number of times left to jump back, or `None` if forever
+    repeats_left: Option<usize>,
+
+    /// Position of the next sample
+    position: usize,
+
+    /// Index of the block in `samples`
+    decoded_block: Option<usize>,
+
+    /// Decoded samples of the current block
+    samples: [[i16; SAMPLES_PER_ADPCM_BLOCK]; MAX_AUDIO_CHANNEL_COUNT]
+}
+
+impl<'a> XboxADPCMLoopingDecoder<'a> {
+    /// Initialize a looping decoder for the given data, channel count and loop points.
+    ///
+    /// `loop_end` is the sample after the last sample of the loop. `repeats` is the number of times to jump back to the
+    /// loop start, or `None` to loop forever. Any incomplete block at the end of `data` is ignored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_channels` is not between 1 and 8, or if the loop points are not inside the data.
+    pub fn new(data: &'a [u8], num_channels: usize, loop_start: usize, loop_end: usize, repeats: Option<usize>) -> XboxADPCMLoopingDecoder<'a> {
+        assert!(num_channels > 0 && num_channels <= MAX_AUDIO_CHANNEL_COUNT, "num_channels must be between 1 and {}", MAX_AUDIO_CHANNEL_COUNT);
+
+        let decoder = XboxADPCMLoopingDecoder {
+            data,
+            num_channels,
+            loop_start,
+            loop_end,
+            repeats_left: repeats,
+            position: 0,
+            decoded_block: None,
+            samples: [[0i16; SAMPLES_PER_ADPCM_BLOCK]; MAX_AUDIO_CHANNEL_COUNT]
+        };
+        assert!(loop_start < loop_end && loop_end <= decoder.total_samples(), "loop points must be inside the data");
+
+        decoder
+    }
+
+    /// Get the number of samples per channel in the data, not counting any loops.
+    pub fn total_samples(&self) -> usize {
+        self.data.len() / (ADPCM_BLOCK_SIZE * self.num_channels) * SAMPLES_PER_ADPCM_BLOCK
+    }
+
+    /// Get the position of the next sample in the data.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Get the number of times left to jump back to the loop start, or `None` if looping forever.
+    pub fn repeats_left(&self) -> Option<usize> {
+        self.repeats_left
+    }
+}
+
+impl<'a> Iterator for XboxADPCMLoopingDecoder<'a> {
+    type Item = PCMFrame;
+
+    fn next(&mut self) -> Option<PCMFrame> {
+        if self.position == self.loop_end && self.repeats_left != Some(0) {
+            self.position = self.loop_start;
+            if let Some(r) = self.repeats_left.as_mut() {
+                *r -= 1;
+            }
+        }
+
+        if self.position >= self.total_samples() {
+            return None
+        }
+
+        // Decode the block if we have not already.
+        let block = self.position / SAMPLES_PER_ADPCM_BLOCK;
+        if self.decoded_block != Some(block) {
+            let block_size = ADPCM_BLOCK_SIZE * self.num_channels;
+            decode_block(self.num_channels, &self.data[block * block_size..(block + 1) * block_size], &mut self.samples);
+            self.decoded_block = Some(block);
+        }
+
+        let offset = self.position % SAMPLES_PER_ADPCM_BLOCK;
+        let mut frame = PCMFrame { samples: [0i16; MAX_AUDIO_CHANNEL_COUNT], num_channels: self.num_channels };
+        for (f, s) in frame.samples.iter_mut().zip(self.samples[..self.num_channels].iter()) {
+            *f = s[offset];
+        }
+
+        self.position += 1;
+        Some(frame)
+    }
+}
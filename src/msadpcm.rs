@@ -0,0 +1,573 @@
+use super::*;
+
+/// Predictor coefficients for Microsoft ADPCM, scaled by 256.
+pub(crate) const MS_ADPCM_COEFFICIENTS: [(i16, i16); 7] = [
+    (256, 0), (512, -256), (0, 0), (192, 64), (240, 0), (460, -208), (392, -232)
+];
+
+/// Factors for adapting the step size after each sample, scaled by 256.
+const MS_ADPCM_ADAPTATION_TABLE: [i32; 16] = [
+    230, 230, 230, 230, 307, 409, 512, 614,
+    768, 614, 512, 409, 307, 230, 230, 230
+];
+
+/// Smallest step size.
+const MS_ADPCM_MIN_DELTA: i32 = 16;
+
+/// Largest step size, so adapting it cannot overflow even with corrupt input.
+const MS_ADPCM_MAX_DELTA: i32 = i32::MAX / 768;
+
+/// Size of a block header for one channel in bytes.
+const MS_ADPCM_HEADER_SIZE: usize = 7;
+
+/// Number of samples the encoder looks at to pick a predictor for a block.
+const MS_ADPCM_PREDICTOR_WINDOW: usize = SAMPLES_PER_ADPCM_BLOCK;
+
+/// Get the number of samples per channel in a Microsoft ADPCM block, or `None` if the block size is invalid.
+///
+/// A block holds a 7-byte header and a whole number of 4-bit samples for each channel, with at least one byte of
+/// samples.
+///
+/// # Example
+///
+/// ```
+/// assert_eq!(xbadpcm::ms_adpcm_samples_per_block(1024, 2), Some(1012));
+/// assert_eq!(xbadpcm::ms_adpcm_samples_per_block(10, 2), None);
+/// ```
+pub fn ms_adpcm_samples_per_block(block_align: usize, num_channels: usize) -> Option<usize> {
+    let header_size = MS_ADPCM_HEADER_SIZE * num_channels;
+    if num_channels == 0 || block_align <= header_size || (block_align - header_size) * 2 % num_channels != 0 {
+        return None
+    }
+    Some(2 + (block_align - header_size) * 2 / num_channels)
+}
+
+/// Get the number of samples per channel in a block, panicking if the block size or the channel count is invalid.
+fn checked_samples_per_block(block_align: usize, num_channels: usize) -> usize {
+    assert!(num_channels > 0 && num_channels <= MAX_AUDIO_CHANNEL_COUNT, "num_channels must be between 1 and {}", MAX_AUDIO_CHANNEL_COUNT);
+    ms_adpcm_samples_per_block(block_align, num_channels).expect("block_align is not a valid Microsoft ADPCM block size for the channel count")
+}
+
+#[derive(Default, Copy, Clone)]
+struct MSADPCMChannel {
+    /// Index of the predictor coefficients
+    predictor: usize,
+
+    /// Step size
+    delta: i32,
+
+    /// Last sample
+    sample1: i32,
+
+    /// Sample before the last sample
+    sample2: i32
+}
+
+impl MSADPCMChannel {
+    /// Predict the next sample.
+    fn predict(&self) -> i32 {
+        let (coef1, coef2) = MS_ADPCM_COEFFICIENTS[self.predictor];
+        (self.sample1 * coef1 as i32 + self.sample2 * coef2 as i32) / 256
+    }
+
+    /// Decode one 4-bit sample.
+    fn decode(&mut self, nibble: u8) -> i16 {
+        let signed = ((nibble << 4) as i8 >> 4) as i32;
+        let sample = (self.predict() as i64 + signed as i64 * self.delta as i64).clamp(i16::MIN as i64, i16::MAX as i64) as i32;
+        self.sample2 = self.sample1;
+        self.sample1 = sample;
+        self.delta = ((MS_ADPCM_ADAPTATION_TABLE[nibble as usize] * self.delta) >> 8).clamp(MS_ADPCM_MIN_DELTA, MS_ADPCM_MAX_DELTA);
+        sample as i16
+    }
+
+    /// Encode one sample, rounding to the nearest step.
+    fn encode(&mut self, sample: i16) -> u8 {
+        let error = sample as i32 - self.predict();
+        let bias = if error < 0 { -self.delta / 2 } else { self.delta / 2 };
+        let nibble = ((error + bias) / self.delta).clamp(-8, 7) as u8 & 0xF;
+        self.decode(nibble);
+        nibble
+    }
+}
+
+/// Microsoft ADPCM (`WAVE_FORMAT_ADPCM`) encoder implementation.
+///
+/// This writes to the same sinks as [`XboxADPCMEncoder`]. Each block uses the predictor that best fits its first 64
+/// samples.
+///
+/// # Example
+///
+/// ```
+/// use xbadpcm::MSADPCMEncoder;
+///
+/// let samples = [1000i16; 1500];
+/// let mut output = Vec::new();
+///
+/// let mut encoder = MSADPCMEncoder::new(2, 1024, &mut output);
+/// encoder.encode(&[&samples, &samples]).unwrap();
+///
+/// // 1012 samples per block
+/// assert_eq!(encoder.finish().unwrap(), 1500 - 1012);
+/// assert_eq!(output.len(), 2048);
+/// ```
+pub struct MSADPCMEncoder<'a, E> {
+    /// Channel state
+    channels: [MSADPCMChannel; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Number of channels
+    num_channels: usize,
+
+    /// Size of a block in bytes
+    block_align: usize,
+
+    /// Number of samples per channel in a block
+    samples_per_block: usize,
+
+    /// Buffer containing the next samples to be processed
+    buffer: [[i16; MS_ADPCM_PREDICTOR_WINDOW]; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Current size of the buffer
+    buffer_size: usize,
+
+    /// Number of samples per channel encoded in the current block, or 0 if the header was not written yet
+    block_samples_encoded: usize,
+
+    /// Number of blocks encoded since the last reset
+    blocks_encoded: usize,
+
+    /// High nibble of a byte that is not complete yet
+    pending_nibble: Option<u8>,
+
+    /// Encoded bytes not yet written to the sink
+    output: [u8; MS_ADPCM_HEADER_SIZE * MAX_AUDIO_CHANNEL_COUNT * 4],
+
+    /// Number of bytes in `output`
+    output_size: usize,
+
+    /// Output buffer
+    sink: &'a mut dyn XboxADPCMEncodeSink<Error = E>
+}
+
+impl<'a, E: Sized> MSADPCMEncoder<'a, E> {
+    /// Initialize an encoder with the given channel count and block size for all channels in bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_channels` is not between 1 and 8, or if `block_align` is not valid according to
+    /// [`ms_adpcm_samples_per_block`].
+    pub fn new(num_channels: usize, block_align: usize, sink: &'a mut dyn XboxADPCMEncodeSink<Error = E>) -> MSADPCMEncoder<'a, E> {
+        let samples_per_block = checked_samples_per_block(block_align, num_channels);
+
+        MSADPCMEncoder {
+            channels: <[MSADPCMChannel; MAX_AUDIO_CHANNEL_COUNT]>::default(),
+            num_channels,
+            block_align,
+            samples_per_block,
+            buffer: [[0i16; MS_ADPCM_PREDICTOR_WINDOW]; MAX_AUDIO_CHANNEL_COUNT],
+            buffer_size: 0,
+            block_samples_encoded: 0,
+            blocks_encoded: 0,
+            pending_nibble: None,
+            output: [0u8; MS_ADPCM_HEADER_SIZE * MAX_AUDIO_CHANNEL_COUNT * 4],
+            output_size: 0,
+            sink
+        }
+    }
+
+    /// Get the number of channels.
+    pub fn num_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    /// Get the size of a block for all channels in bytes.
+    pub fn block_size(&self) -> usize {
+        self.block_align
+    }
+
+    /// Get the number of samples per channel in a block.
+    pub fn samples_per_block(&self) -> usize {
+        self.samples_per_block
+    }
+
+    /// Encode with the given samples.
+    ///
+    /// Note that this may not always encode all samples passed and may store some in a buffer. To flush the buffer, run [`MSADPCMEncoder::finish`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the input has the wrong number of channels or the samples are wrong.
+    pub fn encode<B: AsRef<[C]>, C: AsRef<[i16]>>(&mut self, input: B) -> Result<(), E> {
+        let input_arr = input.as_ref();
+        assert_eq!(self.num_channels, input_arr.len(), "input channel count is incorrect");
+
+        let sample_count = input_arr[0].as_ref().len();
+        for (i, channel) in input_arr.iter().enumerate().skip(1) {
+            assert_eq!(sample_count, channel.as_ref().len(), "sample count of channel {i} does not match the sample count of channel 0");
+        }
+
+        let total_samples_after_this = sample_count + self.block_samples_encoded + self.buffer_size;
+        if total_samples_after_this != 0 {
+            self.sink.reserve(total_samples_after_this.div_ceil(self.samples_per_block) * self.block_align)?;
+        }
+
+        let mut samples_loaded = 0;
+        while samples_loaded != sample_count {
+            let samples_that_can_be_loaded = (MS_ADPCM_PREDICTOR_WINDOW - self.buffer_size).min(sample_count - samples_loaded);
+            for (b, c) in self.buffer.iter_mut().zip(input_arr.iter()) {
+                b[self.buffer_size..self.buffer_size + samples_that_can_be_loaded].copy_from_slice(&c.as_ref()[samples_loaded..samples_loaded + samples_that_can_be_loaded]);
+            }
+            samples_loaded += samples_that_can_be_loaded;
+            self.buffer_size += samples_that_can_be_loaded;
+            self.encode_buffer()?;
+        }
+
+        Ok(())
+    }
+
+    /// Finish encoding and then resets the encoder.
+    ///
+    /// This will encode all remaining samples, filling the rest of the last block with silence.
+    ///
+    /// Returns the number of samples per channel in the last block that came from the input. If no block was written,
+    /// this returns 0.
+    pub fn finish(&mut self) -> Result<usize, E> {
+        let mut real_samples = 0;
+
+        if self.block_samples_encoded != 0 || self.buffer_size != 0 {
+            real_samples = self.block_samples_encoded + self.buffer_size;
+
+            let blocks_encoded = self.blocks_encoded;
+            while self.blocks_encoded == blocks_encoded {
+                let end = MS_ADPCM_PREDICTOR_WINDOW.min(self.samples_per_block - self.block_samples_encoded);
+                for c in &mut self.buffer[..self.num_channels] {
+                    c[self.buffer_size..end].fill(0);
+                }
+                self.buffer_size = end;
+                self.encode_buffer()?;
+            }
+        }
+        else if self.blocks_encoded != 0 {
+            real_samples = self.samples_per_block;
+        }
+
+        self.reset();
+        Ok(real_samples)
+    }
+
+    /// Reset the encoder immediately without writing any more samples.
+    pub fn reset(&mut self) {
+        self.channels = Default::default();
+        self.buffer_size = 0;
+        self.block_samples_encoded = 0;
+        self.blocks_encoded = 0;
+        self.pending_nibble = None;
+        self.output_size = 0;
+    }
+
+    /// Encode as many samples from the buffer as possible.
+    fn encode_buffer(&mut self) -> Result<(), E> {
+        loop {
+            if self.block_samples_encoded == 0 {
+                // Wait until we can see enough of the block to pick a predictor.
+                if self.buffer_size < MS_ADPCM_PREDICTOR_WINDOW.min(self.samples_per_block) {
+                    return Ok(())
+                }
+                self.write_block_header()?;
+            }
+
+            if self.buffer_size == 0 {
+                return Ok(())
+            }
+
+            self.encode_frame()?;
+        }
+    }
+
+    /// Pick the predictors for a block and write its header.
+    fn write_block_header(&mut self) -> Result<(), E> {
+        let window_size = self.buffer_size.min(self.samples_per_block);
+
+        for (ch, buffer) in self.channels[..self.num_channels].iter_mut().zip(self.buffer.iter()) {
+            let delta = ch.delta.clamp(MS_ADPCM_MIN_DELTA, i16::MAX as i32);
+
+            let mut best_error = u64::MAX;
+            for predictor in 0..MS_ADPCM_COEFFICIENTS.len() {
+                let mut trial = MSADPCMChannel { predictor, delta, sample1: buffer[1] as i32, sample2: buffer[0] as i32 };
+                let mut error = 0u64;
+                for &s in &buffer[2..window_size] {
+                    trial.encode(s);
+                    error += ((trial.sample1 - s as i32).unsigned_abs() as u64).pow(2);
+                }
+                if error < best_error {
+                    best_error = error;
+                    *ch = MSADPCMChannel { predictor, delta, sample1: buffer[1] as i32, sample2: buffer[0] as i32 };
+                }
+            }
+        }
+
+        let header_size = MS_ADPCM_HEADER_SIZE * self.num_channels;
+        self.make_room_for_output(header_size)?;
+        let header = &mut self.output[self.output_size..self.output_size + header_size];
+        let n = self.num_channels;
+        for (c, ch) in self.channels[..n].iter().enumerate() {
+            header[c] = ch.predictor as u8;
+            header[n + c * 2..n + c * 2 + 2].copy_from_slice(&(ch.delta as i16).to_le_bytes());
+            header[n * 3 + c * 2..n * 3 + c * 2 + 2].copy_from_slice(&(ch.sample1 as i16).to_le_bytes());
+            header[n * 5 + c * 2..n * 5 + c * 2 + 2].copy_from_slice(&(ch.sample2 as i16).to_le_bytes());
+        }
+        self.output_size += header_size;
+
+        self.consume_samples(2);
+        self.block_samples_encoded = 2;
+        Ok(())
+    }
+
+    /// Encode the next sample of each channel.
+    fn encode_frame(&mut self) -> Result<(), E> {
+        self.make_room_for_output(self.num_channels)?;
+
+        for c in 0..self.num_channels {
+            let nibble = self.channels[c].encode(self.buffer[c][0]);
+            match self.pending_nibble.take() {
+                Some(high) => {
+                    self.output[self.output_size] = (high << 4) | nibble;
+                    self.output_size += 1;
+                },
+                None => self.pending_nibble = Some(nibble)
+            }
+        }
+
+        self.consume_samples(1);
+        self.block_samples_encoded += 1;
+        if self.block_samples_encoded == self.samples_per_block {
+            self.block_samples_encoded = 0;
+            self.blocks_encoded += 1;
+            self.flush_output()?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove samples from the start of the buffer.
+    fn consume_samples(&mut self, sample_count: usize) {
+        for c in &mut self.buffer[..self.num_channels] {
+            c.copy_within(sample_count..self.buffer_size, 0);
+        }
+        self.buffer_size -= sample_count;
+    }
+
+    /// Write the output if there is no room for the given number of bytes.
+    fn make_room_for_output(&mut self, bytes_amount: usize) -> Result<(), E> {
+        if self.output_size + bytes_amount > self.output.len() {
+            self.flush_output()?;
+        }
+        Ok(())
+    }
+
+    /// Write all of the output.
+    fn flush_output(&mut self) -> Result<(), E> {
+        let output_size = self.output_size;
+        self.output_size = 0;
+        self.sink.write(&self.output[..output_size])
+    }
+}
+
+/// Microsoft ADPCM (`WAVE_FORMAT_ADPCM`) decoder implementation.
+///
+/// This writes to the same sinks as [`XboxADPCMDecoder`].
+///
+/// # Example
+///
+/// ```
+/// use xbadpcm::{MSADPCMDecoder, MSADPCMEncoder};
+///
+/// let samples: Vec<i16> = (0..1000).map(|i| ((i as f64 / 10.0).sin() * 8000.0) as i16).collect();
+/// let mut adpcm_data = Vec::new();
+///
+/// let mut encoder = MSADPCMEncoder::new(1, 256, &mut adpcm_data);
+/// encoder.encode(&[&samples]).unwrap();
+/// encoder.finish().unwrap();
+///
+/// let mut output = [Vec::new()];
+/// let mut decoder = MSADPCMDecoder::new(1, 256, &mut output);
+/// decoder.decode(&adpcm_data).unwrap();
+/// decoder.finish().unwrap();
+///
+/// assert_eq!(output[0].len(), 500 * 2);
+/// assert_eq!(output[0][0], samples[0]);
+/// ```
+pub struct MSADPCMDecoder<'a, E> {
+    /// Channel state
+    channels: [MSADPCMChannel; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Number of channels
+    num_channels: usize,
+
+    /// Size of a block in bytes
+    block_align: usize,
+
+    /// Buffer containing the header of the current block
+    header: [u8; MS_ADPCM_HEADER_SIZE * MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Number of bytes of the current block read
+    block_bytes_read: usize,
+
+    /// Channel of the next nibble
+    next_channel: usize,
+
+    /// Decoded samples not yet written
+    samples: [[i16; SAMPLES_PER_ADPCM_BLOCK]; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Number of samples per channel in `samples`
+    samples_size: usize,
+
+    /// Sink
+    sink: &'a mut dyn XboxADPCMDecodeSink<Error = E>
+}
+
+impl<'a, E: Sized> MSADPCMDecoder<'a, E> {
+    /// Initialize a decoder with the given channel count, block size for all channels in bytes, and the output.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_channels` is not between 1 and 8, or if `block_align` is not valid according to
+    /// [`ms_adpcm_samples_per_block`].
+    pub fn new(num_channels: usize, block_align: usize, sink: &'a mut dyn XboxADPCMDecodeSink<Error = E>) -> MSADPCMDecoder<'a, E> {
+        checked_samples_per_block(block_align, num_channels);
+
+        MSADPCMDecoder {
+            channels: <[MSADPCMChannel; MAX_AUDIO_CHANNEL_COUNT]>::default(),
+            num_channels,
+            block_align,
+            header: [0u8; MS_ADPCM_HEADER_SIZE * MAX_AUDIO_CHANNEL_COUNT],
+            block_bytes_read: 0,
+            next_channel: 0,
+            samples: [[0i16; SAMPLES_PER_ADPCM_BLOCK]; MAX_AUDIO_CHANNEL_COUNT],
+            samples_size: 0,
+            sink
+        }
+    }
+
+    /// Get the number of channels.
+    pub fn num_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    /// Get the size of a block for all channels in bytes.
+    pub fn block_size(&self) -> usize {
+        self.block_align
+    }
+
+    /// Get the number of samples per channel in a block.
+    pub fn samples_per_block(&self) -> usize {
+        checked_samples_per_block(self.block_align, self.num_channels)
+    }
+
+    /// Decode the given byte array of Microsoft ADPCM blocks.
+    ///
+    /// Samples are written to the sink 64 at a time. Call [`MSADPCMDecoder::finish`] after the last call to write the
+    /// rest.
+    pub fn decode(&mut self, input: &[u8]) -> Result<(), E> {
+        let blocks_to_reserve = (input.len() + self.block_bytes_read).div_ceil(self.block_align);
+        if blocks_to_reserve > 0 {
+            self.sink.reserve(blocks_to_reserve * self.samples_per_block())?;
+        }
+
+        let header_size = MS_ADPCM_HEADER_SIZE * self.num_channels;
+        for &byte in input {
+            if self.block_bytes_read < header_size {
+                self.header[self.block_bytes_read] = byte;
+                self.block_bytes_read += 1;
+                if self.block_bytes_read == header_size {
+                    self.read_header()?;
+                }
+            }
+            else {
+                self.push_sample(byte >> 4)?;
+                self.push_sample(byte & 0xF)?;
+                self.block_bytes_read += 1;
+            }
+
+            if self.block_bytes_read == self.block_align {
+                self.block_bytes_read = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write any decoded samples that were not written yet and then reset the decoder.
+    ///
+    /// Samples of an incomplete frame at the end of the input are discarded.
+    pub fn finish(&mut self) -> Result<(), E> {
+        let samples_size = self.samples_size;
+        self.reset();
+
+        if samples_size != 0 {
+            for c in &mut self.samples {
+                c[samples_size..].fill(0);
+            }
+            self.sink.write_partial(&self.samples, samples_size)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reset the decoder immediately without writing any more samples.
+    pub fn reset(&mut self) {
+        self.block_bytes_read = 0;
+        self.next_channel = 0;
+        self.samples_size = 0;
+    }
+
+    /// Read the block header and output the two samples stored in it.
+    fn read_header(&mut self) -> Result<(), E> {
+        let n = self.num_channels;
+        let header = &self.header;
+        let read_i16 = |offset: usize| i16::from_le_bytes([header[offset], header[offset + 1]]) as i32;
+
+        for (c, ch) in self.channels[..n].iter_mut().enumerate() {
+            *ch = MSADPCMChannel {
+                predictor: (header[c] as usize).min(MS_ADPCM_COEFFICIENTS.len() - 1),
+                delta: read_i16(n + c * 2),
+                sample1: read_i16(n * 3 + c * 2),
+                sample2: read_i16(n * 5 + c * 2)
+            };
+        }
+
+        self.next_channel = 0;
+        for first in [true, false] {
+            for c in 0..n {
+                let ch = &self.channels[c];
+                self.samples[c][self.samples_size] = if first { ch.sample2 } else { ch.sample1 } as i16;
+            }
+            self.advance_frame()?;
+        }
+
+        Ok(())
+    }
+
+    /// Decode a nibble for the next channel.
+    fn push_sample(&mut self, nibble: u8) -> Result<(), E> {
+        let c = self.next_channel;
+        self.samples[c][self.samples_size] = self.channels[c].decode(nibble);
+
+        self.next_channel += 1;
+        if self.next_channel == self.num_channels {
+            self.next_channel = 0;
+            self.advance_frame()?;
+        }
+
+        Ok(())
+    }
+
+    /// Finish a frame, writing the samples whenever we have a full batch.
+    fn advance_frame(&mut self) -> Result<(), E> {
+        self.samples_size += 1;
+        if self.samples_size == SAMPLES_PER_ADPCM_BLOCK {
+            self.sink.write(&self.samples)?;
+            self.samples_size = 0;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+use core::convert::TryInto;
+
+use super::*;
+
+/// Number of samples per channel needed to write an Xbox ADPCM block, counting the header.
+const WINDOW_CAPACITY: usize = SAMPLES_PER_ADPCM_BLOCK + 1;
+
+/// Sample decoded from the input.
+#[derive(Copy, Clone, Default)]
+struct SourceSample {
+    /// Decoder state after the sample
+    state: ADPCMChannel,
+
+    /// Nibble the sample was decoded from, or `None` if it was a block header or padding
+    nibble: Option<u8>
+}
+
+/// Transcodes standard IMA ADPCM to Xbox ADPCM without decoding and re-encoding everything.
+///
+/// Each Xbox ADPCM block header is taken from the decoder state of the input at that sample, and the input's nibbles
+/// are copied as-is. Only where the input starts a new block, which resets the decoder state to a new header, do
+/// samples have to be re-encoded, and only until the state matches the input again. Everywhere else the output decodes
+/// to exactly the same samples as the input.
+///
+/// Both framings of the input are supported. With [`ADPCMFraming::HeaderSample`], each input header is a sample of its
+/// own, and as with [`XboxADPCMEncoder`], the first sample of the input is only stored in the header of the first
+/// block, so the output decodes to the input without its first sample. With [`ADPCMFraming::Overlapped`], input headers
+/// after the first repeat the sample before them, so they only reset the decoder state, and the output decodes to the
+/// same number of samples as the input.
+///
+/// # Example
+///
+/// ```
+/// use xbadpcm::{ADPCMBlockFormat, IMAToXboxADPCMTranscoder, XboxADPCMEncoder};
+///
+/// let samples: Vec<i16> = (0..4000).map(|i| ((i as f64 / 10.0).sin() * 8000.0) as i16).collect();
+/// let format = ADPCMBlockFormat::ima(1024, 1).unwrap();
+///
+/// let mut ima_data = Vec::new();
+/// let mut encoder = XboxADPCMEncoder::with_format(1, 3, format, &mut ima_data);
+/// encoder.encode(&[&samples]).unwrap();
+/// encoder.finish().unwrap();
+///
+/// let mut xbox_data = Vec::new();
+/// let mut transcoder = IMAToXboxADPCMTranscoder::new(1, format, 3, &mut xbox_data);
+/// transcoder.transcode(&ima_data).unwrap();
+/// let reencoded_samples = transcoder.reencoded_samples();
+/// transcoder.finish().unwrap();
+///
+/// // Only a few samples after each of the IMA ADPCM block headers were re-encoded.
+/// assert!(reencoded_samples < 4 * 16);
+/// ```
+pub struct IMAToXboxADPCMTranscoder<'a, E> {
+    /// Number of channels
+    num_channels: usize,
+
+    /// Block format of the input
+    format: ADPCMBlockFormat,
+
+    /// Lookahead value for re-encoding
+    lookahead: usize,
+
+    /// Buffer containing one word of the input for each channel
+    buffer: [u8; 4 * MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Number of bytes used
+    buffer_size: usize,
+
+    /// Number of words per channel read in the current input block, counting the header
+    block_words_decoded: usize,
+
+    /// Decoder state of the input for each channel
+    channels: [ADPCMChannel; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Decoded samples for the next output block, starting with its header
+    window: [[SourceSample; WINDOW_CAPACITY]; MAX_AUDIO_CHANNEL_COUNT],
+
+    /// Number of samples per channel in `window`
+    window_size: usize,
+
+    /// Number of blocks written since the last reset
+    blocks_written: usize,
+
+    /// Number of samples re-encoded since the transcoder was created, counting each channel
+    reencoded_samples: usize,
+
+    /// Output buffer
+    sink: &'a mut dyn XboxADPCMEncodeSink<Error = E>
+}
+
+impl<'a, E: Sized> IMAToXboxADPCMTranscoder<'a, E> {
+    /// Initialize a transcoder with the given channel count, block format of the input, and lookahead used for
+    /// re-encoding samples.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_channels` is not between 1 and 8.
+    pub fn new(num_channels: usize, format: ADPCMBlockFormat, lookahead: u8, sink: &'a mut dyn XboxADPCMEncodeSink<Error = E>) -> IMAToXboxADPCMTranscoder<'a, E> {
+        assert!(num_channels > 0 && num_channels <= MAX_AUDIO_CHANNEL_COUNT, "num_channels must be between 1 and {}", MAX_AUDIO_CHANNEL_COUNT);
+
+        IMAToXboxADPCMTranscoder {
+            num_channels,
+            format,
+            lookahead: lookahead as usize,
+            buffer: [0u8; 4 * MAX_AUDIO_CHANNEL_COUNT],
+            buffer_size: 0,
+            block_words_decoded: 0,
+            channels: <[ADPCMChannel; MAX_AUDIO_CHANNEL_COUNT]>::default(),
+            window: [[SourceSample::default(); WINDOW_CAPACITY]; MAX_AUDIO_CHANNEL_COUNT],
+            window_size: 0,
+            blocks_written: 0,
+            reencoded_samples: 0,
+            sink
+        }
+    }
+
+    /// Transcode the given bytes of IMA ADPCM blocks.
+    ///
+    /// Note that this may keep some samples in a buffer. To flush the buffer, run [`IMAToXboxADPCMTranscoder::finish`].
+    pub fn transcode(&mut self, input: &[u8]) -> Result<(), E> {
+        let input_len = input.len();
+        let word_size = 4 * self.num_channels;
+
+        // Predict how many bytes we will need to reserve, always rounding up to the next block.
+        let samples_after_this = self.window_size + (self.buffer_size + input_len) / word_size * SAMPLES_PER_CHUNK;
+        if samples_after_this != 0 {
+            self.sink.reserve(samples_after_this.div_ceil(SAMPLES_PER_ADPCM_BLOCK) * ADPCM_BLOCK_SIZE * self.num_channels)?;
+        }
+
+        let mut bytes_loaded = 0;
+        while bytes_loaded != input_len {
+            let bytes_that_can_be_loaded = (word_size - self.buffer_size).min(input_len - bytes_loaded);
+            self.buffer[self.buffer_size..self.buffer_size + bytes_that_can_be_loaded].copy_from_slice(&input[bytes_loaded..bytes_loaded + bytes_that_can_be_loaded]);
+            self.buffer_size += bytes_that_can_be_loaded;
+            bytes_loaded += bytes_that_can_be_loaded;
+            if self.buffer_size == word_size {
+                self.read_words()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finish transcoding and then reset the transcoder.
+    ///
+    /// The rest of the last block repeats the last sample. Bytes of an incomplete word at the end of the input are
+    /// discarded.
+    ///
+    /// Returns the number of samples per channel in the last block that came from the input, counted as they are output
+    /// by the decoder, the same as [`XboxADPCMEncoder::finish`].
+    pub fn finish(&mut self) -> Result<usize, E> {
+        let mut real_samples = 0;
+
+        // After the first block, the first sample in the window is the last sample of the previous block.
+        if self.window_size > (self.blocks_written != 0) as usize {
+            real_samples = self.window_size - 1;
+            for c in &mut self.window[..self.num_channels] {
+                let padding = SourceSample { state: c[self.window_size - 1].state, nibble: None };
+                c[self.window_size..].fill(padding);
+            }
+            self.window_size = WINDOW_CAPACITY;
+            self.write_block()?;
+        }
+        else if self.blocks_written != 0 {
+            real_samples = SAMPLES_PER_ADPCM_BLOCK;
+        }
+
+        self.reset();
+        Ok(real_samples)
+    }
+
+    /// Reset the transcoder immediately without writing any more samples.
+    pub fn reset(&mut self) {
+        self.buffer_size = 0;
+        self.block_words_decoded = 0;
+        self.window_size = 0;
+        self.blocks_written = 0;
+    }
+
+    /// Get the number of samples that had to be re-encoded since the transcoder was created, counting each channel.
+    pub fn reencoded_samples(&self) -> usize {
+        self.reencoded_samples
+    }
+
+    /// Read one word for each channel from the buffer.
+    fn read_words(&mut self) -> Result<(), E> {
+        let mut samples = [[SourceSample::default(); SAMPLES_PER_CHUNK]; MAX_AUDIO_CHANNEL_COUNT];
+        let sample_count;
+
+        if self.block_words_decoded == 0 {
+            for ((ch, header), s) in self.channels[..self.num_channels].iter_mut().zip(self.buffer.chunks_exact(4)).zip(samples.iter_mut()) {
+                *ch = read_header(header);
+                s[0] = SourceSample { state: *ch, nibble: None };
+            }
+
+            // Overlapped headers after the first repeat the previous sample, so they replace it instead of adding one.
+            if self.format.framing() == ADPCMFraming::Overlapped && self.window_size != 0 {
+                for (w, s) in self.window.iter_mut().zip(samples.iter()) {
+                    w[self.window_size - 1] = s[0];
+                }
+                sample_count = 0;
+            }
+            else {
+                sample_count = 1;
+            }
+        }
+        else {
+            for ((ch, word), s) in self.channels[..self.num_channels].iter_mut().zip(self.buffer.chunks_exact(4)).zip(samples.iter_mut()) {
+                let word = u32::from_le_bytes(word.try_into().unwrap());
+                for (i, s) in s.iter_mut().enumerate() {
+                    let nibble = ((word >> (i * 4)) & 0xF) as u8;
+                    decode_nibble(ch, nibble, ADPCMDeltaMode::ShiftAdd);
+                    *s = SourceSample { state: *ch, nibble: Some(nibble) };
+                }
+            }
+            sample_count = SAMPLES_PER_CHUNK;
+        }
+
+        self.buffer_size = 0;
+        self.block_words_decoded += 1;
+        if self.block_words_decoded * 4 == self.format.channel_block_size() {
+            self.block_words_decoded = 0;
+        }
+
+        for i in 0..sample_count {
+            for (w, s) in self.window.iter_mut().zip(samples.iter()) {
+                w[self.window_size] = s[i];
+            }
+            self.window_size += 1;
+
+            if self.window_size == WINDOW_CAPACITY {
+                self.write_block()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write an Xbox ADPCM block from the window.
+    fn write_block(&mut self) -> Result<(), E> {
+        const BYTES_PER_CHANNEL_PER_CHUNK: usize = SAMPLES_PER_CHUNK / 2;
+
+        let mut bytes_to_write = [0u8; ADPCM_BLOCK_SIZE * MAX_AUDIO_CHANNEL_COUNT];
+        let total_bytes_to_write = ADPCM_BLOCK_SIZE * self.num_channels;
+        let output_channel_stride = self.num_channels * BYTES_PER_CHANNEL_PER_CHUNK;
+        let mut reencoded_samples = 0;
+
+        for (ch, window) in self.window[..self.num_channels].iter().enumerate() {
+            // The header matches the input exactly.
+            let mut state = window[0].state;
+            write_header(&state, &mut bytes_to_write[ch * 4..]);
+
+            let mut targets = [0i16; WINDOW_CAPACITY];
+            for (t, s) in targets.iter_mut().zip(window.iter()) {
+                *t = s.state.pcmdata as i16;
+            }
+
+            // Copy nibbles while our state matches the input, and re-encode the samples after a header until it does again.
+            let mut in_sync = true;
+            for (i, source) in window.iter().enumerate().skip(1) {
+                let nibble = match source.nibble {
+                    Some(n) if in_sync => {
+                        decode_nibble(&mut state, n, ADPCMDeltaMode::ShiftAdd);
+                        n
+                    },
+                    _ => {
+                        reencoded_samples += 1;
+                        let n = encode_sample(&mut state, &mut [0, 0], self.lookahead, &targets[i..], NibbleSearch { delta_mode: ADPCMDeltaMode::ShiftAdd, metric: ErrorMetric::Squared });
+                        in_sync = state.pcmdata == source.state.pcmdata && state.index == source.state.index;
+                        n
+                    }
+                };
+
+                let sample = i - 1;
+                let offset = self.num_channels * 4 + (sample / SAMPLES_PER_CHUNK) * output_channel_stride + ch * BYTES_PER_CHANNEL_PER_CHUNK + (sample % SAMPLES_PER_CHUNK) / 2;
+                bytes_to_write[offset] |= nibble << ((sample % 2) * 4);
+            }
+        }
+
+        self.reencoded_samples += reencoded_samples;
+        self.blocks_written += 1;
+
+        // The last sample of this block is the header of the next one.
+        for c in &mut self.window[..self.num_channels] {
+            c[0] = c[SAMPLES_PER_ADPCM_BLOCK];
+        }
+        self.window_size = 1;
+
+        self.sink.write(&bytes_to_write[..total_bytes_to_write])
+    }
+}
@@ -1,3 +1,5 @@
+use super::ADPCMDeltaMode;
+
 /// Number of samples per chunk
 pub(crate) const SAMPLES_PER_CHUNK: usize = 8;
 
@@ -33,7 +35,8 @@ pub(crate) const INDEX_TABLE: [isize; 16] = [
     -1, -1, -1, -1, 2, 4, 6, 8
 ];
 
-#[derive(Default, Copy, Clone)]
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub(crate) struct ADPCMChannel {
     pub pcmdata: i32,
     pub index: usize
@@ -50,12 +53,182 @@ pub(crate) fn clamp_table_index(index: isize) -> usize {
 }
 
 /// Calculate sample delta to decode an ADPCM sample.
-pub(crate) fn calculate_delta(step: u16, code: u8) -> i32 {
+pub(crate) fn calculate_delta(step: u16, code: u8, delta_mode: ADPCMDeltaMode) -> i32 {
     let step = step as i32;
-    let mut delta = step >> 3;
-    if (code & 1) != 0 { delta += step >> 2; }
-    if (code & 2) != 0 { delta += step >> 1; }
-    if (code & 4) != 0 { delta += step; }
+    let mut delta = match delta_mode {
+        ADPCMDeltaMode::ShiftAdd => {
+            let mut delta = step >> 3;
+            if (code & 1) != 0 { delta += step >> 2; }
+            if (code & 2) != 0 { delta += step >> 1; }
+            if (code & 4) != 0 { delta += step; }
+            delta
+        },
+        ADPCMDeltaMode::Multiply => ((2 * (code & 7) as i32 + 1) * step) >> 3
+    };
     if (code & 8) != 0 { delta = -delta; }
     delta
 }
+
+/// Read a block header for one channel.
+pub(crate) fn read_header(header: &[u8]) -> ADPCMChannel {
+    ADPCMChannel {
+        pcmdata: i16::from_le_bytes([header[0], header[1]]) as i32,
+        index: clamp_table_index(header[2] as isize)
+    }
+}
+
+/// Write a block header for one channel.
+pub(crate) fn write_header(channel: &ADPCMChannel, header: &mut [u8]) {
+    header[0..2].copy_from_slice(&(channel.pcmdata as i16).to_le_bytes());
+    header[2] = channel.index as u8;
+    header[3] = 0;
+}
+
+/// Decode one 4-bit sample.
+pub(crate) fn decode_nibble(channel: &mut ADPCMChannel, nibble: u8, delta_mode: ADPCMDeltaMode) -> i16 {
+    channel.pcmdata = clamp_sample(channel.pcmdata + calculate_delta(STEP_TABLE[channel.index], nibble, delta_mode));
+    channel.index = clamp_table_index(channel.index as isize + INDEX_TABLE[nibble as usize]);
+    channel.pcmdata as i16
+}
+
+/// Decode the eight samples of a word, starting with the lowest four bits.
+pub(crate) fn decode_word(channel: &mut ADPCMChannel, mut word: u32, output: &mut [i16], delta_mode: ADPCMDeltaMode) {
+    for o in output[..SAMPLES_PER_CHUNK].iter_mut() {
+        *o = decode_nibble(channel, (word & 0xF) as u8, delta_mode);
+        word >>= 4;
+    }
+}
+
+impl ADPCMChannel {
+    /// Check that the sample and step index are in range.
+    pub(crate) fn is_valid(&self) -> bool {
+        clamp_sample(self.pcmdata) == self.pcmdata && self.index < STEP_TABLE.len()
+    }
+}
+
+/// Bytes held back by an encoder or decoder for a block layout other than `ADPCMBlockLayout::Interleaved`.
+#[cfg(feature = "std")]
+pub(crate) type LayoutBuffer = std::vec::Vec<u8>;
+
+/// Bytes held back by an encoder or decoder for a block layout other than `ADPCMBlockLayout::Interleaved`.
+///
+/// Those layouts need the `"std"` feature, so this is always empty. It is still serialized as an empty sequence so saved
+/// states have the same fields either way, and a state holding bytes is rejected when it is deserialized.
+#[cfg(not(feature = "std"))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct LayoutBuffer;
+
+#[cfg(not(feature = "std"))]
+impl LayoutBuffer {
+    pub(crate) const fn new() -> LayoutBuffer {
+        LayoutBuffer
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        0
+    }
+}
+
+#[cfg(all(feature = "serde", not(feature = "std")))]
+impl serde::Serialize for LayoutBuffer {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeSeq;
+        serializer.serialize_seq(Some(0))?.end()
+    }
+}
+
+#[cfg(all(feature = "serde", not(feature = "std")))]
+impl<'de> serde::Deserialize<'de> for LayoutBuffer {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use core::fmt;
+        use serde::de::{Error, SeqAccess, Visitor};
+
+        struct EmptyVisitor;
+
+        impl<'de> Visitor<'de> for EmptyVisitor {
+            type Value = LayoutBuffer;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an empty sequence of bytes")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<LayoutBuffer, A::Error> {
+                match seq.next_element::<u8>()? {
+                    Some(_) => Err(A::Error::custom("held back layout bytes need the std feature")),
+                    None => Ok(LayoutBuffer)
+                }
+            }
+        }
+
+        deserializer.deserialize_seq(EmptyVisitor)
+    }
+}
+
+/// Serialize arrays that are too long for serde to derive.
+#[cfg(feature = "serde")]
+pub(crate) mod serde_arrays {
+    use core::fmt;
+    use serde::de::{Deserialize, Error, SeqAccess, Visitor};
+    use serde::ser::{Serialize, SerializeTuple};
+    use serde::{Deserializer, Serializer};
+
+    /// Deserialize `len` elements into `output`.
+    fn deserialize_elements<'de, 'a, D: Deserializer<'de>, T: Deserialize<'de> + 'a, I: Iterator<Item = &'a mut T>>(deserializer: D, len: usize, output: I) -> Result<(), D::Error> {
+        struct ElementsVisitor<I>(usize, I);
+
+        impl<'de, 'a, T: Deserialize<'de> + 'a, I: Iterator<Item = &'a mut T>> Visitor<'de> for ElementsVisitor<I> {
+            type Value = ();
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                write!(formatter, "an array of {} elements", self.0)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+                for (i, e) in self.1.enumerate() {
+                    *e = seq.next_element()?.ok_or_else(|| A::Error::invalid_length(i, &"more elements"))?;
+                }
+                Ok(())
+            }
+        }
+
+        deserializer.deserialize_tuple(len, ElementsVisitor(len, output))
+    }
+
+    /// Arrays of any length.
+    pub mod flat {
+        use super::*;
+
+        pub fn serialize<S: Serializer, T: Serialize, const N: usize>(array: &[T; N], serializer: S) -> Result<S::Ok, S::Error> {
+            let mut tuple = serializer.serialize_tuple(N)?;
+            for e in array {
+                tuple.serialize_element(e)?;
+            }
+            tuple.end()
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de> + Copy + Default, const N: usize>(deserializer: D) -> Result<[T; N], D::Error> {
+            let mut array = [T::default(); N];
+            deserialize_elements(deserializer, N, array.iter_mut())?;
+            Ok(array)
+        }
+    }
+
+    /// Arrays of arrays of any length, such as samples for each channel, flattened into one array.
+    pub mod nested {
+        use super::*;
+
+        pub fn serialize<S: Serializer, T: Serialize, const N: usize, const C: usize>(array: &[[T; N]; C], serializer: S) -> Result<S::Ok, S::Error> {
+            let mut tuple = serializer.serialize_tuple(N * C)?;
+            for e in array.iter().flat_map(|a| a.iter()) {
+                tuple.serialize_element(e)?;
+            }
+            tuple.end()
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de> + Copy + Default, const N: usize, const C: usize>(deserializer: D) -> Result<[[T; N]; C], D::Error> {
+            let mut array = [[T::default(); N]; C];
+            deserialize_elements(deserializer, N * C, array.iter_mut().flat_map(|a| a.iter_mut()))?;
+            Ok(array)
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use core::convert::TryFrom;
+
+use super::*;
+
+/// WAVE format tag for 16-bit or 8-bit PCM.
+pub const WAVE_FORMAT_PCM: u16 = 0x0001;
+
+/// WAVE format tag for Microsoft ADPCM.
+pub const WAVE_FORMAT_ADPCM: u16 = 0x0002;
+
+/// WAVE format tag for Microsoft IMA ADPCM.
+pub const WAVE_FORMAT_IMA_ADPCM: u16 = 0x0011;
+
+/// WAVE format tag for Xbox ADPCM.
+pub const WAVE_FORMAT_XBOX_ADPCM: u16 = 0x0069;
+
+/// Format of the audio in a WAV file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WavFormat {
+    /// WAVE format tag
+    pub format_tag: u16,
+
+    /// Number of channels
+    pub channels: u16,
+
+    /// Samples per second
+    pub sample_rate: u32,
+
+    /// Size of one block for all channels in bytes
+    pub block_align: u16,
+
+    /// Bits per sample
+    pub bits_per_sample: u16,
+
+    /// Samples per block, if the format is compressed
+    pub samples_per_block: Option<u16>
+}
+
+impl WavFormat {
+    /// Get the format for Xbox ADPCM with the given channel count and sample rate.
+    pub fn xbox_adpcm(channels: u16, sample_rate: u32) -> WavFormat {
+        WavFormat {
+            format_tag: WAVE_FORMAT_XBOX_ADPCM,
+            channels,
+            sample_rate,
+            block_align: ADPCM_BLOCK_SIZE as u16 * channels,
+            bits_per_sample: 4,
+            samples_per_block: Some(SAMPLES_PER_ADPCM_BLOCK as u16)
+        }
+    }
+
+    /// Get the format for standard IMA ADPCM with the given channel count, sample rate and block format.
+    pub fn ima_adpcm(channels: u16, sample_rate: u32, format: ADPCMBlockFormat) -> WavFormat {
+        WavFormat {
+            format_tag: WAVE_FORMAT_IMA_ADPCM,
+            channels,
+            sample_rate,
+            block_align: format.block_size(channels as usize) as u16,
+            bits_per_sample: 4,
+            samples_per_block: Some(format.samples_per_block() as u16)
+        }
+    }
+
+    /// Get the format for Microsoft ADPCM with the given channel count, sample rate and block size for all channels.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `block_align` is not valid according to [`ms_adpcm_samples_per_block`].
+    pub fn ms_adpcm(channels: u16, sample_rate: u32, block_align: u16) -> WavFormat {
+        let samples_per_block = ms_adpcm_samples_per_block(block_align as usize, channels as usize).expect("block_align is not a valid Microsoft ADPCM block size for the channel count");
+        WavFormat {
+            format_tag: WAVE_FORMAT_ADPCM,
+            channels,
+            sample_rate,
+            block_align,
+            bits_per_sample: 4,
+            samples_per_block: Some(samples_per_block as u16)
+        }
+    }
+
+    /// Get the format for PCM with the given channel count, sample rate and bits per sample.
+    pub fn pcm(channels: u16, sample_rate: u32, bits_per_sample: u16) -> WavFormat {
+        WavFormat {
+            format_tag: WAVE_FORMAT_PCM,
+            channels,
+            sample_rate,
+            block_align: bits_per_sample / 8 * channels,
+            bits_per_sample,
+            samples_per_block: None
+        }
+    }
+
+    /// Get the block format to decode the data with, if the format is Xbox ADPCM or IMA ADPCM.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use xbadpcm::{ADPCMBlockFormat, WavFormat};
+    ///
+    /// let format = ADPCMBlockFormat::ima(1024, 2).unwrap();
+    /// let wav_format = WavFormat::ima_adpcm(2, 44100, format);
+    ///
+    /// assert_eq!(wav_format.samples_per_block, Some(1017));
+    /// assert_eq!(wav_format.adpcm_block_format(), Some(format));
+    /// ```
+    pub fn adpcm_block_format(&self) -> Option<ADPCMBlockFormat> {
+        match self.format_tag {
+            WAVE_FORMAT_XBOX_ADPCM => Some(ADPCMBlockFormat::XBOX),
+            WAVE_FORMAT_IMA_ADPCM => ADPCMBlockFormat::ima(self.block_align as usize, self.channels as usize),
+            _ => None
+        }
+    }
+
+    /// Get the average number of bytes per second.
+    pub fn bytes_per_second(&self) -> u32 {
+        let samples_per_block = self.samples_per_block.unwrap_or(1) as u64;
+        (self.sample_rate as u64 * self.block_align as u64 / samples_per_block) as u32
+    }
+}
+
+/// Write a WAV file with the given format and data to the sink.
+///
+/// For Microsoft ADPCM, the standard predictor coefficients are also written.
+///
+/// # Panics
+///
+/// Panics if the data is too large to fit in a WAV file.
+pub fn write_wav<E>(sink: &mut dyn XboxADPCMEncodeSink<Error = E>, format: &WavFormat, data: &[u8]) -> Result<(), E> {
+    let extra_size: u32 = match (format.samples_per_block, format.format_tag) {
+        (Some(_), WAVE_FORMAT_ADPCM) => 4 + 2 + 4 * MS_ADPCM_COEFFICIENTS.len() as u32,
+        (Some(_), _) => 4,
+        (None, _) => 0
+    };
+    let fmt_size: u32 = 16 + extra_size;
+    let padding = data.len() % 2;
+    let riff_size = u32::try_from(4 + (8 + fmt_size as usize) + (8 + data.len() + padding)).expect("data is too large for a WAV file");
+
+    sink.reserve(8 + riff_size as usize)?;
+
+    sink.write(b"RIFF")?;
+    sink.write(&riff_size.to_le_bytes())?;
+    sink.write(b"WAVE")?;
+
+    sink.write(b"fmt ")?;
+    sink.write(&fmt_size.to_le_bytes())?;
+    sink.write(&format.format_tag.to_le_bytes())?;
+    sink.write(&format.channels.to_le_bytes())?;
+    sink.write(&format.sample_rate.to_le_bytes())?;
+    sink.write(&format.bytes_per_second().to_le_bytes())?;
+    sink.write(&format.block_align.to_le_bytes())?;
+    sink.write(&format.bits_per_sample.to_le_bytes())?;
+    if let Some(samples_per_block) = format.samples_per_block {
+        sink.write(&(extra_size as u16 - 2).to_le_bytes())?;
+        sink.write(&samples_per_block.to_le_bytes())?;
+        if format.format_tag == WAVE_FORMAT_ADPCM {
+            sink.write(&(MS_ADPCM_COEFFICIENTS.len() as u16).to_le_bytes())?;
+            for (coef1, coef2) in MS_ADPCM_COEFFICIENTS {
+                sink.write(&coef1.to_le_bytes())?;
+                sink.write(&coef2.to_le_bytes())?;
+            }
+        }
+    }
+
+    sink.write(b"data")?;
+    sink.write(&(data.len() as u32).to_le_bytes())?;
+    sink.write(data)?;
+    if padding != 0 {
+        sink.write(&[0])?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,469 @@
+//! Reader for XACT wave banks (.xwb) made for the original Xbox.
+//!
+//! This supports the little endian wave bank layout used by XACT for the original Xbox (file versions 2 and 3):
+//!
+//! | Offset | Size | Field                                                                            |
+//! |--------|------|----------------------------------------------------------------------------------|
+//! | 0x00   | 4    | Signature (`WBND`)                                                               |
+//! | 0x04   | 4    | Version                                                                          |
+//! | 0x08   | 32   | Offset and size of the bank data, entry metadata, entry name and wave data segments |
+//!
+//! The bank data segment contains the bank flags, the entry count, a 16 byte bank name, the size of each entry metadata
+//! and entry name element, the alignment of the wave data, and the format used by compact banks.
+//!
+//! Each entry metadata element contains the entry flags (lowest 4 bits) and duration in samples (highest 28 bits), the
+//! format, the offset and size of the entry in the wave data segment, and the offset and size of the loop region in
+//! bytes relative to the start of the entry.
+//!
+//! The format is a bit field containing the format tag (bits 0-1), channel count (bits 2-4), sample rate (bits 5-30)
+//! and whether samples are 16-bit (bit 31).
+
+use core::convert::TryInto;
+use core::fmt;
+
+use super::*;
+
+/// Signature at the start of every wave bank.
+pub const WAVE_BANK_SIGNATURE: [u8; 4] = *b"WBND";
+
+/// Bank flag set if the bank is meant to be streamed instead of loaded into memory.
+pub const WAVE_BANK_FLAG_STREAMING: u32 = 0x00000001;
+
+/// Bank flag set if the bank contains entry names.
+pub const WAVE_BANK_FLAG_ENTRY_NAMES: u32 = 0x00010000;
+
+/// Bank flag set if the bank uses the compact entry format, which is not supported.
+pub const WAVE_BANK_FLAG_COMPACT: u32 = 0x00020000;
+
+/// Version written by [`WaveBankBuilder`].
+#[cfg(feature = "std")]
+pub(crate) const WAVE_BANK_VERSION: u32 = 3;
+
+/// Size of the header, including the segment table.
+pub(crate) const WAVE_BANK_HEADER_SIZE: usize = 0x28;
+
+/// Size of the bank data segment.
+pub(crate) const WAVE_BANK_DATA_SIZE: usize = 0x28;
+
+/// Size of an entry metadata element.
+pub(crate) const WAVE_BANK_ENTRY_SIZE: usize = 0x18;
+
+/// Length of the bank name in bytes.
+pub(crate) const WAVE_BANK_NAME_LENGTH: usize = 16;
+
+/// Length of an entry name in bytes.
+#[cfg(feature = "std")]
+pub(crate) const WAVE_BANK_ENTRY_NAME_LENGTH: usize = 64;
+
+/// Largest entry duration in samples, which is stored in the highest 28 bits of the entry metadata.
+#[cfg(feature = "std")]
+pub(crate) const WAVE_BANK_MAX_DURATION: usize = (1 << 28) - 1;
+
+/// Error returned when a wave bank could not be read.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WaveBankError {
+    /// The data does not start with [`WAVE_BANK_SIGNATURE`].
+    InvalidSignature,
+
+    /// The wave bank version is not supported.
+    UnsupportedVersion(u32),
+
+    /// The wave bank uses the compact entry format, which is not supported.
+    CompactFormat,
+
+    /// A segment, entry or name is outside of the data.
+    OutOfBounds
+}
+
+impl fmt::Display for WaveBankError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaveBankError::InvalidSignature => f.write_str("not a wave bank"),
+            WaveBankError::UnsupportedVersion(v) => write!(f, "unsupported wave bank version {v}"),
+            WaveBankError::CompactFormat => f.write_str("compact wave banks are not supported"),
+            WaveBankError::OutOfBounds => f.write_str("wave bank data is out of bounds")
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for WaveBankError {}
+
+/// Format tag of a wave bank entry.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WaveBankFormatTag {
+    /// PCM
+    PCM,
+
+    /// Xbox ADPCM
+    XboxADPCM,
+
+    /// Windows Media Audio
+    WMA,
+
+    /// Unknown format tag
+    Unknown(u8)
+}
+
+impl WaveBankFormatTag {
+    pub(crate) fn from_bits(bits: u32) -> WaveBankFormatTag {
+        match bits {
+            0 => WaveBankFormatTag::PCM,
+            1 => WaveBankFormatTag::XboxADPCM,
+            2 => WaveBankFormatTag::WMA,
+            n => WaveBankFormatTag::Unknown(n as u8)
+        }
+    }
+
+    pub(crate) fn to_bits(self) -> u32 {
+        match self {
+            WaveBankFormatTag::PCM => 0,
+            WaveBankFormatTag::XboxADPCM => 1,
+            WaveBankFormatTag::WMA => 2,
+            WaveBankFormatTag::Unknown(n) => n as u32 & 0x3
+        }
+    }
+}
+
+/// Audio format of a wave bank entry.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WaveBankFormat {
+    /// Format tag
+    pub tag: WaveBankFormatTag,
+
+    /// Number of channels
+    pub channels: u8,
+
+    /// Samples per second
+    pub sample_rate: u32,
+
+    /// Bits per sample for PCM entries (8 or 16)
+    pub bits_per_sample: u8
+}
+
+impl WaveBankFormat {
+    /// Read the format from its bit field.
+    pub fn from_bits(bits: u32) -> WaveBankFormat {
+        WaveBankFormat {
+            tag: WaveBankFormatTag::from_bits(bits & 0x3),
+            channels: ((bits >> 2) & 0x7) as u8,
+            sample_rate: (bits >> 5) & 0x3FFFFFF,
+            bits_per_sample: if (bits >> 31) != 0 { 16 } else { 8 }
+        }
+    }
+
+    /// Get the format as a bit field.
+    pub fn to_bits(&self) -> u32 {
+        self.tag.to_bits()
+            | ((self.channels as u32 & 0x7) << 2)
+            | ((self.sample_rate & 0x3FFFFFF) << 5)
+            | (((self.bits_per_sample == 16) as u32) << 31)
+    }
+
+    /// Get the size of one frame (or block, for Xbox ADPCM) in bytes and the number of samples in it.
+    ///
+    /// Returns `None` for WMA and unknown formats.
+    pub fn block(&self) -> Option<(usize, usize)> {
+        match self.tag {
+            WaveBankFormatTag::PCM => Some((self.channels as usize * self.bits_per_sample as usize / 8, 1)),
+            WaveBankFormatTag::XboxADPCM => Some((ADPCM_BLOCK_SIZE * self.channels as usize, SAMPLES_PER_ADPCM_BLOCK)),
+            _ => None
+        }
+    }
+
+    /// Get the format to use when exporting this to a WAV file.
+    ///
+    /// Returns `None` for WMA and unknown formats.
+    pub fn wav_format(&self) -> Option<WavFormat> {
+        match self.tag {
+            WaveBankFormatTag::PCM => Some(WavFormat::pcm(self.channels as u16, self.sample_rate, self.bits_per_sample as u16)),
+            WaveBankFormatTag::XboxADPCM => Some(WavFormat::xbox_adpcm(self.channels as u16, self.sample_rate)),
+            _ => None
+        }
+    }
+}
+
+/// Offset and size of a region in bytes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WaveBankRegion {
+    /// Offset in bytes
+    pub offset: u32,
+
+    /// Size in bytes
+    pub length: u32
+}
+
+impl WaveBankRegion {
+    fn read(data: &[u8], offset: usize) -> WaveBankRegion {
+        WaveBankRegion {
+            offset: read_u32(data, offset),
+            length: read_u32(data, offset + 4)
+        }
+    }
+
+    fn slice<'a>(&self, data: &'a [u8]) -> Result<&'a [u8], WaveBankError> {
+        let start = self.offset as usize;
+        let end = start.checked_add(self.length as usize).ok_or(WaveBankError::OutOfBounds)?;
+        data.get(start..end).ok_or(WaveBankError::OutOfBounds)
+    }
+}
+
+/// Wave bank read from memory.
+///
+/// # Example
+///
+/// ```no_run
+/// use xbadpcm::WaveBank;
+///
+/// let data = std::fs::read("sounds.xwb").unwrap();
+/// let bank = WaveBank::parse(&data).unwrap();
+///
+/// for entry in bank.entries() {
+///     let entry = entry.unwrap();
+///     let mut wav = Vec::new();
+///     entry.write_wav(&mut wav).unwrap();
+///     std::fs::write(format!("{}.wav", entry.name.unwrap_or("unnamed")), wav).unwrap();
+/// }
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct WaveBank<'a> {
+    /// Version of the file
+    version: u32,
+
+    /// Bank flags
+    flags: u32,
+
+    /// Bank name
+    name: &'a [u8],
+
+    /// Number of entries
+    entry_count: usize,
+
+    /// Alignment of entries in the wave data segment
+    alignment: u32,
+
+    /// Entry metadata segment
+    entry_metadata: &'a [u8],
+
+    /// Size of each entry metadata element
+    entry_metadata_size: usize,
+
+    /// Entry names segment, if present
+    entry_names: Option<&'a [u8]>,
+
+    /// Size of each entry name
+    entry_name_size: usize,
+
+    /// Wave data segment
+    wave_data: &'a [u8]
+}
+
+impl<'a> WaveBank<'a> {
+    /// Read a wave bank.
+    pub fn parse(data: &'a [u8]) -> Result<WaveBank<'a>, WaveBankError> {
+        if data.len() < WAVE_BANK_HEADER_SIZE {
+            return Err(if data.starts_with(&WAVE_BANK_SIGNATURE) { WaveBankError::OutOfBounds } else { WaveBankError::InvalidSignature });
+        }
+        if data[0..4] != WAVE_BANK_SIGNATURE {
+            return Err(WaveBankError::InvalidSignature);
+        }
+
+        let version = read_u32(data, 0x04);
+        if !(2..=3).contains(&version) {
+            return Err(WaveBankError::UnsupportedVersion(version));
+        }
+
+        let bank_data = WaveBankRegion::read(data, 0x08).slice(data)?;
+        let entry_metadata = WaveBankRegion::read(data, 0x10).slice(data)?;
+        let entry_names = WaveBankRegion::read(data, 0x18).slice(data)?;
+        let wave_data = WaveBankRegion::read(data, 0x20).slice(data)?;
+
+        if bank_data.len() < WAVE_BANK_DATA_SIZE {
+            return Err(WaveBankError::OutOfBounds);
+        }
+
+        let flags = read_u32(bank_data, 0x00);
+        if flags & WAVE_BANK_FLAG_COMPACT != 0 {
+            return Err(WaveBankError::CompactFormat);
+        }
+
+        let entry_count = read_u32(bank_data, 0x04) as usize;
+        let name = &bank_data[0x08..0x08 + WAVE_BANK_NAME_LENGTH];
+        let entry_metadata_size = read_u32(bank_data, 0x18) as usize;
+        let entry_name_size = read_u32(bank_data, 0x1C) as usize;
+        let alignment = read_u32(bank_data, 0x20);
+
+        if entry_metadata_size < WAVE_BANK_ENTRY_SIZE || entry_count.checked_mul(entry_metadata_size).is_none_or(|s| s > entry_metadata.len()) {
+            return Err(WaveBankError::OutOfBounds);
+        }
+
+        let entry_names = if flags & WAVE_BANK_FLAG_ENTRY_NAMES != 0 && entry_name_size != 0 {
+            if entry_count.checked_mul(entry_name_size).is_none_or(|s| s > entry_names.len()) {
+                return Err(WaveBankError::OutOfBounds);
+            }
+            Some(entry_names)
+        }
+        else {
+            None
+        };
+
+        Ok(WaveBank {
+            version,
+            flags,
+            name,
+            entry_count,
+            alignment,
+            entry_metadata,
+            entry_metadata_size,
+            entry_names,
+            entry_name_size,
+            wave_data
+        })
+    }
+
+    /// Get the version of the file.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Get the bank flags.
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Get whether the bank is meant to be streamed.
+    pub fn is_streaming(&self) -> bool {
+        self.flags & WAVE_BANK_FLAG_STREAMING != 0
+    }
+
+    /// Get the bank name.
+    ///
+    /// Returns `None` if the name is not valid UTF-8.
+    pub fn name(&self) -> Option<&'a str> {
+        read_string(self.name)
+    }
+
+    /// Get the alignment of entries in the wave data segment in bytes.
+    pub fn alignment(&self) -> u32 {
+        self.alignment
+    }
+
+    /// Get the number of entries.
+    pub fn len(&self) -> usize {
+        self.entry_count
+    }
+
+    /// Get whether the bank has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entry_count == 0
+    }
+
+    /// Get the entry at the given index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn entry(&self, index: usize) -> Result<WaveBankEntry<'a>, WaveBankError> {
+        assert!(index < self.entry_count, "entry index {} is out of bounds", index);
+
+        let metadata = &self.entry_metadata[index * self.entry_metadata_size..];
+        let flags_and_duration = read_u32(metadata, 0x00);
+        let play_region = WaveBankRegion::read(metadata, 0x08);
+        let loop_region = WaveBankRegion::read(metadata, 0x10);
+
+        let name = self.entry_names
+            .and_then(|n| read_string(&n[index * self.entry_name_size..(index + 1) * self.entry_name_size]))
+            .filter(|n| !n.is_empty());
+
+        Ok(WaveBankEntry {
+            name,
+            flags: flags_and_duration & 0xF,
+            duration: flags_and_duration >> 4,
+            format: WaveBankFormat::from_bits(read_u32(metadata, 0x04)),
+            play_region,
+            loop_region,
+            data: play_region.slice(self.wave_data)?
+        })
+    }
+
+    /// Iterate through all entries.
+    pub fn entries(&self) -> impl Iterator<Item = Result<WaveBankEntry<'a>, WaveBankError>> + 'a {
+        let bank = *self;
+        (0..self.entry_count).map(move |i| bank.entry(i))
+    }
+}
+
+/// Entry of a wave bank.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WaveBankEntry<'a> {
+    /// Name of the entry, if the bank has entry names and the name is valid UTF-8 and not empty
+    pub name: Option<&'a str>,
+
+    /// Entry flags
+    pub flags: u32,
+
+    /// Duration in samples
+    pub duration: u32,
+
+    /// Audio format
+    pub format: WaveBankFormat,
+
+    /// Region of the entry in the wave data segment
+    pub play_region: WaveBankRegion,
+
+    /// Region of the loop in bytes, relative to the start of the entry
+    ///
+    /// This has a length of 0 if the entry does not loop.
+    pub loop_region: WaveBankRegion,
+
+    /// Audio data
+    pub data: &'a [u8]
+}
+
+impl<'a> WaveBankEntry<'a> {
+    /// Get the loop start and loop end in samples, or `None` if the entry does not loop or its format is not supported.
+    ///
+    /// For Xbox ADPCM entries, these are counted as they are output by [`XboxADPCMDecoder`], so they can be used with
+    /// [`XboxADPCMLoopingDecoder`].
+    pub fn loop_samples(&self) -> Option<(usize, usize)> {
+        let (block_size, samples_per_block) = self.format.block()?;
+        if self.loop_region.length == 0 || block_size == 0 {
+            return None
+        }
+
+        let start = self.loop_region.offset as usize;
+        let end = start + self.loop_region.length as usize;
+        Some((start / block_size * samples_per_block, end / block_size * samples_per_block))
+    }
+
+    /// Decode the entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the entry is not Xbox ADPCM or has an invalid channel count.
+    pub fn decode<E: Sized>(&self, sink: &mut dyn XboxADPCMDecodeSink<Error = E>) -> Result<(), E> {
+        assert_eq!(self.format.tag, WaveBankFormatTag::XboxADPCM, "entry is not Xbox ADPCM");
+        XboxADPCMDecoder::new(self.format.channels as usize, sink).decode(self.data)
+    }
+
+    /// Write the entry as a WAV file without converting it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`WaveBankFormat::wav_format`] returns `None` for the entry's format.
+    pub fn write_wav<E>(&self, sink: &mut dyn XboxADPCMEncodeSink<Error = E>) -> Result<(), E> {
+        let format = self.format.wav_format().expect("entry format cannot be exported to WAV");
+        write_wav(sink, &format, self.data)
+    }
+}
+
+/// Read a little endian 32-bit integer.
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Read a string padded with null bytes.
+fn read_string(data: &[u8]) -> Option<&str> {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    core::str::from_utf8(&data[..end]).ok()
+}
@@ -0,0 +1,287 @@
+use core::convert::TryFrom;
+use std::string::String;
+use std::vec::Vec;
+
+use super::*;
+
+/// Entry to be written by a [`WaveBankBuilder`].
+#[derive(Clone, Debug)]
+struct WaveBankBuilderEntry {
+    /// Name of the entry
+    name: Option<String>,
+
+    /// Duration in samples
+    duration: u32,
+
+    /// Audio format
+    format: WaveBankFormat,
+
+    /// Loop region in bytes, relative to the start of the entry
+    loop_region: WaveBankRegion,
+
+    /// Audio data
+    data: Vec<u8>
+}
+
+/// Builder for writing XACT wave banks for the original Xbox.
+///
+/// The wave banks written can be read with [`WaveBank`]. See the [`WaveBank`] documentation for the layout used.
+///
+/// # Example
+///
+/// ```
+/// use xbadpcm::{WaveBank, WaveBankBuilder};
+///
+/// let samples: Vec<i16> = (0..1000).map(|i| ((i as f64 / 10.0).sin() * 8000.0) as i16).collect();
+///
+/// let mut builder = WaveBankBuilder::new("music");
+/// builder.add_pcm(Some("theme"), 44100, &[&samples], 3, Some((100, 900)));
+/// let bank_data = builder.to_bytes();
+///
+/// let bank = WaveBank::parse(&bank_data).unwrap();
+/// let entry = bank.entry(0).unwrap();
+/// assert_eq!(entry.name, Some("theme"));
+/// assert_eq!(entry.format.sample_rate, 44100);
+/// assert!(entry.loop_samples().is_some());
+/// ```
+#[derive(Clone, Debug)]
+pub struct WaveBankBuilder {
+    /// Bank name
+    name: String,
+
+    /// Whether the bank is meant to be streamed
+    streaming: bool,
+
+    /// Alignment of entries in the wave data segment
+    alignment: u32,
+
+    /// Entries to write
+    entries: Vec<WaveBankBuilderEntry>
+}
+
+impl WaveBankBuilder {
+    /// Initialize a builder for a bank with the given name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is longer than 15 bytes.
+    pub fn new(name: &str) -> WaveBankBuilder {
+        assert!(name.len() < WAVE_BANK_NAME_LENGTH, "bank name must be shorter than {} bytes", WAVE_BANK_NAME_LENGTH);
+
+        WaveBankBuilder {
+            name: name.into(),
+            streaming: false,
+            alignment: 4,
+            entries: Vec::new()
+        }
+    }
+
+    /// Set whether the bank is meant to be streamed instead of loaded into memory.
+    ///
+    /// This is `false` by default.
+    pub fn set_streaming(&mut self, streaming: bool) {
+        self.streaming = streaming;
+    }
+
+    /// Set the alignment of entries in the wave data segment in bytes.
+    ///
+    /// This is 4 by default. Streaming banks are usually aligned to the 2048 byte sectors of the disc.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the alignment is 0.
+    pub fn set_alignment(&mut self, alignment: u32) {
+        assert!(alignment > 0, "alignment must be at least 1");
+        self.alignment = alignment;
+    }
+
+    /// Get the number of entries added so far.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Get whether no entries were added yet.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add an entry containing already encoded Xbox ADPCM data.
+    ///
+    /// `loop_samples` is the loop start and loop end in samples as they are output by [`XboxADPCMDecoder`]. Loop regions
+    /// are stored in whole blocks, so both loop points must be at block boundaries, such as the ones returned by
+    /// [`XboxADPCMEncoder::encode_looped`]. Any incomplete block at the end of the data is not written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `channels` is not between 1 and 7, if the loop points are not at block boundaries inside the data, or if
+    /// the data is 2^28 samples or longer.
+    pub fn add_adpcm(&mut self, name: Option<&str>, sample_rate: u32, channels: u8, data: &[u8], loop_samples: Option<(usize, usize)>) {
+        let block_size = ADPCM_BLOCK_SIZE * channels as usize;
+        let duration = data.len() / block_size.max(1) * SAMPLES_PER_ADPCM_BLOCK;
+        self.push_adpcm(name, sample_rate, channels, &data[..data.len() / block_size.max(1) * block_size], duration, loop_samples);
+    }
+
+    /// Encode PCM samples with [`XboxADPCMEncoder`] and add them as an entry.
+    ///
+    /// The input is given per channel, the same as [`XboxADPCMEncoder::encode`]. If `loop_samples` is set, it is the loop
+    /// start and loop end in the input, and the input is encoded with [`XboxADPCMEncoder::encode_looped`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the input does not have between 1 and 7 channels, if the channels have different lengths, if the loop
+    /// points are not inside the input, or if the encoded entry is 2^28 samples or longer.
+    pub fn add_pcm<B: AsRef<[C]>, C: AsRef<[i16]>>(&mut self, name: Option<&str>, sample_rate: u32, input: B, lookahead: u8, loop_samples: Option<(usize, usize)>) {
+        let channels = input.as_ref().len();
+        assert!(channels > 0 && channels < 8, "channel count must be between 1 and 7");
+
+        let mut data = Vec::new();
+        let mut encoder = XboxADPCMEncoder::new(channels, lookahead, &mut data);
+
+        let (duration, loop_samples) = match loop_samples {
+            Some((loop_start, loop_end)) => {
+                let encoded_loop = encoder.encode_looped(input, loop_start, loop_end).unwrap().expect("encoding without an observer is never cancelled");
+                (encoded_loop.loop_end, Some((encoded_loop.loop_start, encoded_loop.loop_end)))
+            },
+            None => {
+                encoder.encode(input).unwrap();
+                let last_block_samples = encoder.finish().unwrap();
+                let block_count = data.len() / (ADPCM_BLOCK_SIZE * channels);
+                (block_count.saturating_sub(1) * SAMPLES_PER_ADPCM_BLOCK + last_block_samples, None)
+            }
+        };
+
+        self.push_adpcm(name, sample_rate, channels as u8, &data, duration, loop_samples);
+    }
+
+    /// Add an Xbox ADPCM entry.
+    fn push_adpcm(&mut self, name: Option<&str>, sample_rate: u32, channels: u8, data: &[u8], duration: usize, loop_samples: Option<(usize, usize)>) {
+        assert!(channels > 0 && channels < 8, "channel count must be between 1 and 7");
+        assert!(duration <= WAVE_BANK_MAX_DURATION, "entry duration must be less than 2^28 samples");
+
+        let block_size = ADPCM_BLOCK_SIZE * channels as usize;
+        let loop_region = match loop_samples {
+            Some((loop_start, loop_end)) => {
+                assert!(loop_start % SAMPLES_PER_ADPCM_BLOCK == 0 && loop_end % SAMPLES_PER_ADPCM_BLOCK == 0, "loop points must be at block boundaries");
+                let start_block = loop_start / SAMPLES_PER_ADPCM_BLOCK;
+                let end_block = loop_end / SAMPLES_PER_ADPCM_BLOCK;
+                assert!(loop_start < loop_end && end_block * block_size <= data.len(), "loop points must be inside the data");
+                WaveBankRegion {
+                    offset: (start_block * block_size) as u32,
+                    length: ((end_block - start_block) * block_size) as u32
+                }
+            },
+            None => WaveBankRegion::default()
+        };
+
+        self.entries.push(WaveBankBuilderEntry {
+            name: name.map(String::from),
+            duration: duration as u32,
+            format: WaveBankFormat {
+                tag: WaveBankFormatTag::XboxADPCM,
+                channels,
+                sample_rate,
+                bits_per_sample: 16
+            },
+            loop_region,
+            data: data.to_vec()
+        });
+    }
+
+    /// Write the wave bank.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an entry name is longer than 63 bytes, or if the bank is larger than 4 GiB.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let entry_count = self.entries.len();
+        let has_names = self.entries.iter().any(|e| e.name.is_some());
+        let name_size = if has_names { WAVE_BANK_ENTRY_NAME_LENGTH } else { 0 };
+        let alignment = self.alignment as usize;
+
+        let bank_data_offset = WAVE_BANK_HEADER_SIZE;
+        let entry_metadata_offset = bank_data_offset + WAVE_BANK_DATA_SIZE;
+        let entry_metadata_size = entry_count * WAVE_BANK_ENTRY_SIZE;
+        let entry_names_offset = entry_metadata_offset + entry_metadata_size;
+        let entry_names_size = entry_count * name_size;
+        let wave_data_offset = (entry_names_offset + entry_names_size).next_multiple_of(alignment);
+
+        // Lay out the wave data.
+        let mut play_regions = Vec::with_capacity(entry_count);
+        let mut wave_data_size: usize = 0;
+        for e in &self.entries {
+            wave_data_size = wave_data_size.next_multiple_of(alignment);
+            play_regions.push(WaveBankRegion { offset: wave_data_size as u32, length: e.data.len() as u32 });
+            wave_data_size += e.data.len();
+        }
+        assert!(u32::try_from(wave_data_offset + wave_data_size).is_ok(), "wave bank is too large");
+
+        let mut output = Vec::with_capacity(wave_data_offset + wave_data_size);
+
+        // Header
+        output.extend_from_slice(&WAVE_BANK_SIGNATURE);
+        write_u32(&mut output, WAVE_BANK_VERSION as usize);
+        for (offset, size) in [
+            (bank_data_offset, WAVE_BANK_DATA_SIZE),
+            (entry_metadata_offset, entry_metadata_size),
+            (entry_names_offset, entry_names_size),
+            (wave_data_offset, wave_data_size)
+        ] {
+            write_u32(&mut output, offset);
+            write_u32(&mut output, size);
+        }
+
+        // Bank data
+        let mut flags = 0;
+        if self.streaming {
+            flags |= WAVE_BANK_FLAG_STREAMING;
+        }
+        if has_names {
+            flags |= WAVE_BANK_FLAG_ENTRY_NAMES;
+        }
+        write_u32(&mut output, flags as usize);
+        write_u32(&mut output, entry_count);
+        write_padded_string(&mut output, &self.name, WAVE_BANK_NAME_LENGTH);
+        write_u32(&mut output, WAVE_BANK_ENTRY_SIZE);
+        write_u32(&mut output, name_size);
+        write_u32(&mut output, alignment);
+        write_u32(&mut output, 0);
+
+        // Entry metadata
+        for (e, play_region) in self.entries.iter().zip(play_regions.iter()) {
+            write_u32(&mut output, (e.duration as usize) << 4);
+            write_u32(&mut output, e.format.to_bits() as usize);
+            write_u32(&mut output, play_region.offset as usize);
+            write_u32(&mut output, play_region.length as usize);
+            write_u32(&mut output, e.loop_region.offset as usize);
+            write_u32(&mut output, e.loop_region.length as usize);
+        }
+
+        // Entry names
+        if has_names {
+            for e in &self.entries {
+                write_padded_string(&mut output, e.name.as_deref().unwrap_or(""), WAVE_BANK_ENTRY_NAME_LENGTH);
+            }
+        }
+
+        // Wave data
+        for (e, play_region) in self.entries.iter().zip(play_regions.iter()) {
+            output.resize(wave_data_offset + play_region.offset as usize, 0);
+            output.extend_from_slice(&e.data);
+        }
+        output.resize(wave_data_offset + wave_data_size, 0);
+
+        output
+    }
+}
+
+/// Write a little endian 32-bit integer.
+fn write_u32(output: &mut Vec<u8>, value: usize) {
+    output.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+/// Write a string padded with null bytes, always leaving at least one null byte at the end.
+fn write_padded_string(output: &mut Vec<u8>, string: &str, length: usize) {
+    assert!(string.len() < length, "name \"{}\" must be shorter than {} bytes", string, length);
+    output.extend_from_slice(string.as_bytes());
+    output.resize(output.len() + length - string.len(), 0);
+}
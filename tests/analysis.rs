@@ -0,0 +1,70 @@
+//! Checks the statistics reported when comparing decoded audio with the original.
+
+extern crate xbadpcm;
+
+use xbadpcm::{ADPCMBlockFormat, XboxADPCMEncoder, compare_adpcm, compare_adpcm_with_format, compare_pcm};
+
+fn pcm(length: usize) -> Vec<i16> {
+    (0..length).map(|i| ((i as f64 / 20.0).sin() * 8000.0) as i16).collect()
+}
+
+#[test]
+fn channel_statistics() {
+    let original = [1000i16, -1000, 1000, -1000];
+    let decoded = [1000i16, -1000, 1010, -1000];
+    let report = compare_pcm([&original], [&decoded]);
+
+    let channel = report.channels[0];
+    assert_eq!(channel.samples, 4);
+    assert_eq!(channel.max_error, 10);
+    assert_eq!(channel.rms_error, 5.0);
+    assert_eq!(channel.snr, 10.0 * (4_000_000.0f64 / 100.0).log10());
+    assert_eq!(report.min_snr(), Some(channel.snr));
+}
+
+#[test]
+fn shorter_input_limits_comparison() {
+    let original = pcm(200);
+    let report = compare_pcm([&original], [&original[..130]]);
+    assert_eq!(report.channels[0].samples, 130);
+    assert_eq!(report.blocks.iter().map(|b| b.channels[0].samples).collect::<Vec<_>>(), [64, 64, 2]);
+}
+
+#[test]
+fn worst_block() {
+    let original = pcm(256);
+    let mut decoded = original.clone();
+    decoded[150] += 500;
+    let report = compare_pcm([&original, &original], [&original, &decoded]);
+
+    assert_eq!(report.worst_block(1).unwrap().block, 2);
+    assert_eq!(report.worst_block_overall().unwrap().block, 2);
+    assert_eq!(report.worst_block(0).unwrap().channels[0].snr, f64::INFINITY);
+    assert_eq!(report.worst_block(2), None);
+    assert_eq!(compare_pcm([&[0i16; 0]], [&[0i16; 0]]).worst_block(0), None);
+}
+
+#[test]
+fn blocks_follow_format() {
+    let original = pcm(4096);
+    for &format in &[ADPCMBlockFormat::XBOX, ADPCMBlockFormat::ima(256, 1).unwrap(), ADPCMBlockFormat::ima(1024, 1).unwrap()] {
+        let mut adpcm = Vec::new();
+        let mut encoder = XboxADPCMEncoder::with_format(1, 3, format, &mut adpcm);
+        encoder.encode([&original]).unwrap();
+        encoder.finish().unwrap();
+
+        let report = compare_adpcm_with_format([&original], &adpcm, format);
+        let block_count = adpcm.len() / format.block_size(1);
+        assert_eq!(report.blocks.len(), block_count);
+        for block in &report.blocks[..block_count - 1] {
+            assert_eq!(block.channels[0].samples, format.samples_per_block());
+        }
+        assert!(report.min_snr().unwrap() > 20.0);
+    }
+
+    let mut adpcm = Vec::new();
+    let mut encoder = XboxADPCMEncoder::new(1, 3, &mut adpcm);
+    encoder.encode([&original]).unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(compare_adpcm([&original], &adpcm), compare_adpcm_with_format([&original], &adpcm, ADPCMBlockFormat::XBOX));
+}
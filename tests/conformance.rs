@@ -0,0 +1,101 @@
+//! Checks decoding against the test vectors in `tests/vectors`, which were decoded with the IMA ADPCM reference decoder
+//! by `tests/vectors/generate.py`.
+
+extern crate xbadpcm;
+
+use xbadpcm::{ADPCMBlockFormat, ADPCMConformance, ADPCMDeltaMode, ADPCMFraming, XboxADPCMDecoder, XboxADPCMDecodeSink};
+
+struct PlanarSink(Vec<Vec<i16>>);
+
+impl XboxADPCMDecodeSink for PlanarSink {
+    type Error = ();
+
+    fn write(&mut self, samples: &[[i16; 64]]) -> Result<(), ()> {
+        for (c, s) in self.0.iter_mut().zip(samples.iter()) {
+            c.extend_from_slice(s);
+        }
+        Ok(())
+    }
+
+    fn write_partial(&mut self, samples: &[[i16; 64]], samples_amount: usize) -> Result<(), ()> {
+        for (c, s) in self.0.iter_mut().zip(samples.iter()) {
+            c.extend_from_slice(&s[..samples_amount]);
+        }
+        Ok(())
+    }
+}
+
+fn decode(adpcm: &[u8], num_channels: usize, format: ADPCMBlockFormat) -> Vec<Vec<i16>> {
+    decode_with(adpcm, num_channels, format, ADPCMConformance::IMAReference, ADPCMDeltaMode::ShiftAdd)
+}
+
+fn decode_with(adpcm: &[u8], num_channels: usize, format: ADPCMBlockFormat, conformance: ADPCMConformance, delta_mode: ADPCMDeltaMode) -> Vec<Vec<i16>> {
+    let mut sink = PlanarSink(vec![Vec::new(); num_channels]);
+    let mut decoder = XboxADPCMDecoder::with_format(num_channels, format, &mut sink);
+    decoder.set_conformance(conformance);
+    decoder.set_delta_mode(delta_mode);
+    decoder.decode(adpcm).unwrap();
+    decoder.finish().unwrap();
+    sink.0
+}
+
+fn expected(pcm: &[u8], num_channels: usize) -> Vec<Vec<i16>> {
+    let mut channels = vec![Vec::new(); num_channels];
+    for (i, s) in pcm.chunks_exact(2).enumerate() {
+        channels[i % num_channels].push(i16::from_le_bytes([s[0], s[1]]));
+    }
+    channels
+}
+
+fn check(name: &str, adpcm: &[u8], pcm: &[u8], num_channels: usize, channel_block_size: usize) {
+    let format = ADPCMBlockFormat::ima(channel_block_size * num_channels, num_channels).unwrap();
+    let expected = expected(pcm, num_channels);
+    assert_eq!(decode(adpcm, num_channels, format), expected, "{} does not match the reference", name);
+    assert_eq!(decode_with(adpcm, num_channels, format, ADPCMConformance::Native, ADPCMDeltaMode::ShiftAdd), expected, "{} does not match the reference with native arithmetic", name);
+
+    // Without the header samples, the same blocks decode to the reference output with the first sample of each block removed.
+    let spb = format.samples_per_block();
+    let overlapped: Vec<Vec<i16>> = expected.iter().map(|c| c.chunks(spb).flat_map(|b| b[1..].iter().copied()).collect()).collect();
+    let format = format.with_framing(ADPCMFraming::Overlapped);
+    assert_eq!(decode(adpcm, num_channels, format), overlapped, "{} does not match the reference with overlapped framing", name);
+}
+
+macro_rules! vector {
+    ($test:ident, $name:expr, $num_channels:expr, $channel_block_size:expr) => {
+        #[test]
+        fn $test() {
+            check(
+                $name,
+                include_bytes!(concat!("vectors/", $name, ".adpcm")),
+                include_bytes!(concat!("vectors/", $name, ".pcm")),
+                $num_channels,
+                $channel_block_size
+            );
+        }
+    };
+}
+
+vector!(mono_36, "mono_36", 1, 36);
+vector!(stereo_36, "stereo_36", 2, 36);
+vector!(six_channels_36, "6ch_36", 6, 36);
+vector!(clamp_36, "clamp_36", 2, 36);
+vector!(mono_256, "mono_256", 1, 256);
+vector!(stereo_1024, "stereo_1024", 2, 1024);
+
+#[test]
+fn xbox_format_matches_ima_format() {
+    let adpcm = include_bytes!("vectors/stereo_36.adpcm");
+    let ima = ADPCMBlockFormat::ima(72, 2).unwrap().with_framing(ADPCMFraming::Overlapped);
+    assert_eq!(decode(adpcm, 2, ADPCMBlockFormat::XBOX), decode(adpcm, 2, ima));
+}
+
+#[test]
+fn reference_conformance_ignores_delta_mode() {
+    let adpcm = include_bytes!("vectors/mono_36.adpcm");
+    let format = ADPCMBlockFormat::XBOX;
+    let reference = decode(adpcm, 1, format);
+
+    assert_eq!(decode_with(adpcm, 1, format, ADPCMConformance::IMAReference, ADPCMDeltaMode::Multiply), reference);
+    assert_eq!(decode_with(adpcm, 1, format, ADPCMConformance::Native, ADPCMDeltaMode::ShiftAdd), reference);
+    assert_ne!(decode_with(adpcm, 1, format, ADPCMConformance::Native, ADPCMDeltaMode::Multiply), reference);
+}
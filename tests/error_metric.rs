@@ -0,0 +1,122 @@
+//! Checks that the error metric changes which 4-bit samples are chosen, and that weighted errors are remembered within
+//! a block but not across blocks.
+
+#[cfg(feature = "serde")]
+extern crate serde_json;
+extern crate xbadpcm;
+
+use xbadpcm::{ErrorMetric, XboxADPCMDecoder, XboxADPCMEncoder};
+
+const STEP_INDEX_CHANGE: [i32; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+
+fn pcm(length: usize) -> Vec<i16> {
+    let mut noise = 1u32;
+    (0..length).map(|i| {
+        noise = noise.wrapping_mul(1664525).wrapping_add(1013904223);
+        let tone = (i as f64 / 7.0).sin() * 6000.0 + (i as f64 / 2.3).sin() * 2000.0;
+        (tone + (noise >> 20) as f64 - 2048.0) as i16
+    }).collect()
+}
+
+fn encode(pcm: &[i16], metric: ErrorMetric) -> Vec<u8> {
+    let mut adpcm = Vec::new();
+    let mut encoder = XboxADPCMEncoder::new(1, 3, &mut adpcm);
+    encoder.set_error_metric(metric);
+    encoder.encode([pcm]).unwrap();
+    encoder.finish().unwrap();
+    adpcm
+}
+
+/// Sum the cost of the error of each decoded sample, restarting the weighting at the start of each block.
+fn total_cost(pcm: &[i16], adpcm: &[u8], [a, b]: [i16; 2]) -> f64 {
+    let mut decoded = [Vec::new()];
+    let mut decoder = XboxADPCMDecoder::new(1, &mut decoded);
+    decoder.decode(adpcm).unwrap();
+    decoder.finish().unwrap();
+
+    // The first input sample is only stored in the header of the first block.
+    let mut total = 0.0;
+    for (block_pcm, block_decoded) in pcm[1..].chunks(64).zip(decoded[0].chunks(64)) {
+        let mut history = [0.0; 2];
+        for (&p, &d) in block_pcm.iter().zip(block_decoded.iter()) {
+            let error = d as f64 - p as f64;
+            let filtered = error + (a as f64 * history[0] + b as f64 * history[1]) / 256.0;
+            total += filtered * filtered;
+            history = [error, history[0]];
+        }
+    }
+    total
+}
+
+#[test]
+fn zero_weights_are_squared_error() {
+    let pcm = pcm(5000);
+    assert_eq!(encode(&pcm, ErrorMetric::Weighted([0, 0])), encode(&pcm, ErrorMetric::Squared));
+}
+
+#[test]
+fn each_metric_minimizes_its_own_cost() {
+    let pcm = pcm(5000);
+    let squared = encode(&pcm, ErrorMetric::Squared);
+    let weighted = encode(&pcm, ErrorMetric::PERCEPTUAL);
+    assert_ne!(squared, weighted);
+
+    assert!(total_cost(&pcm, &squared, [0, 0]) < total_cost(&pcm, &weighted, [0, 0]));
+    assert!(total_cost(&pcm, &weighted, [0, -128]) < total_cost(&pcm, &squared, [0, -128]));
+}
+
+#[test]
+fn loop_start_is_primed_with_same_metric() {
+    let pcm = pcm(3000);
+    let mut adpcm = Vec::new();
+    let mut encoder = XboxADPCMEncoder::new(1, 3, &mut adpcm);
+    encoder.set_error_metric(ErrorMetric::PERCEPTUAL);
+    let encoded_loop = encoder.encode_looped([&pcm[..]], 500, 2900).unwrap().unwrap();
+
+    // Follow the step index from the header of the block with the last sample of the loop to that sample.
+    let last_sample = encoded_loop.loop_end - 1;
+    let block = &adpcm[last_sample / 64 * 36..][..36];
+    let mut step_index = block[2] as i32;
+    for i in 0..=last_sample % 64 {
+        let nibble = block[4 + i / 2] >> (i % 2 * 4) & 0xF;
+        step_index = (step_index + STEP_INDEX_CHANGE[nibble as usize & 7]).clamp(0, 88);
+    }
+
+    assert_eq!(adpcm[encoded_loop.loop_start_block * 36 + 2] as i32, step_index);
+}
+
+/// Encode the rest of the input after resuming from the state, with the error history of the channel replaced.
+#[cfg(feature = "serde")]
+fn resume_with_history(state: &xbadpcm::XboxADPCMEncoderState, rest: &[i16], history: Option<[i32; 2]>) -> Vec<u8> {
+    let mut state = serde_json::to_value(state).unwrap();
+    if let Some(history) = history {
+        state["error_history"][0] = serde_json::json!(history);
+    }
+    let state = serde_json::from_value(state).unwrap();
+
+    let mut adpcm = Vec::new();
+    let mut encoder = XboxADPCMEncoder::from_state(&state, &mut adpcm).unwrap();
+    encoder.encode([rest]).unwrap();
+    encoder.finish().unwrap();
+    adpcm
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn history_carries_across_chunks_but_not_blocks() {
+    let pcm = pcm(1000);
+
+    // 100 samples leaves the encoder in the middle of the second block, and 70 samples leaves it between the first and
+    // second blocks since a block needs 12 samples to start with a lookahead of 3.
+    for &(split, in_block) in &[(100, true), (70, false)] {
+        let mut adpcm = Vec::new();
+        let mut encoder = XboxADPCMEncoder::new(1, 3, &mut adpcm);
+        encoder.set_error_metric(ErrorMetric::PERCEPTUAL);
+        encoder.encode([&pcm[..split]]).unwrap();
+        let state = encoder.state();
+
+        let resumed = resume_with_history(&state, &pcm[split..], None);
+        let reweighted = resume_with_history(&state, &pcm[split..], Some([4000, 4000]));
+        assert_eq!(resumed != reweighted, in_block, "split at {}", split);
+    }
+}
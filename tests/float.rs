@@ -0,0 +1,42 @@
+//! Checks that dithered floating point input encodes the same however it is passed in.
+
+extern crate xbadpcm;
+
+use xbadpcm::{FloatConversionMode, FloatConverter, XboxADPCMEncoder};
+
+fn float_pcm(length: usize, phase: f64) -> Vec<f32> {
+    (0..length).map(|i| ((i as f64 / 20.0 + phase).sin() * 0.3) as f32).collect()
+}
+
+fn converter() -> FloatConverter {
+    FloatConverter::with_seed(FloatConversionMode::TriangularDither, 1234)
+}
+
+#[test]
+fn dither_does_not_depend_on_chunking() {
+    let left = float_pcm(1000, 0.0);
+    let right = float_pcm(1000, 1.0);
+    let interleaved: Vec<f32> = left.iter().zip(right.iter()).flat_map(|(&l, &r)| [l, r]).collect();
+
+    let mut whole = Vec::new();
+    let mut encoder = XboxADPCMEncoder::new(2, 3, &mut whole);
+    encoder.encode_float([&left, &right], &mut converter()).unwrap();
+    encoder.finish().unwrap();
+
+    let mut split = Vec::new();
+    let mut split_converter = converter();
+    let mut encoder = XboxADPCMEncoder::new(2, 3, &mut split);
+    for range in [0..1, 1..300, 300..1000] {
+        encoder.encode_float([&left[range.clone()], &right[range]], &mut split_converter).unwrap();
+    }
+    encoder.finish().unwrap();
+    assert_eq!(split, whole);
+
+    let mut interleaved_split = Vec::new();
+    let mut interleaved_converter = converter();
+    let mut encoder = XboxADPCMEncoder::new(2, 3, &mut interleaved_split);
+    encoder.encode_float_interleaved(&interleaved[..777], &mut interleaved_converter).unwrap();
+    encoder.encode_float_interleaved(&interleaved[777..], &mut interleaved_converter).unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(interleaved_split, whole);
+}
@@ -0,0 +1,96 @@
+//! Checks that streams in every block layout decode the same as interleaved streams, however the input is split.
+
+extern crate xbadpcm;
+
+use xbadpcm::{ADPCMBlockFormat, ADPCMBlockLayout, XboxADPCMDecoder, XboxADPCMDecodeSink, XboxADPCMEncoder};
+
+const LAYOUTS: [ADPCMBlockLayout; 5] = [ADPCMBlockLayout::Interleaved, ADPCMBlockLayout::Planar, ADPCMBlockLayout::Stride(1), ADPCMBlockLayout::Stride(3), ADPCMBlockLayout::Stride(64)];
+
+struct PlanarSink(Vec<Vec<i16>>);
+
+impl XboxADPCMDecodeSink for PlanarSink {
+    type Error = ();
+
+    fn write(&mut self, samples: &[[i16; 64]]) -> Result<(), ()> {
+        for (c, s) in self.0.iter_mut().zip(samples.iter()) {
+            c.extend_from_slice(s);
+        }
+        Ok(())
+    }
+
+    fn write_partial(&mut self, samples: &[[i16; 64]], samples_amount: usize) -> Result<(), ()> {
+        for (c, s) in self.0.iter_mut().zip(samples.iter()) {
+            c.extend_from_slice(&s[..samples_amount]);
+        }
+        Ok(())
+    }
+}
+
+fn pcm(num_channels: usize, length: usize) -> Vec<Vec<i16>> {
+    (0..num_channels).map(|c| (0..length).map(|i| ((i as f64 / (7.0 + c as f64)).sin() * 8000.0) as i16).collect()).collect()
+}
+
+fn encode(pcm: &[Vec<i16>], format: ADPCMBlockFormat, layout: ADPCMBlockLayout) -> Vec<u8> {
+    let mut adpcm = Vec::new();
+    let mut encoder = XboxADPCMEncoder::with_format(pcm.len(), 2, format, &mut adpcm);
+    encoder.set_layout(layout);
+    encoder.encode(pcm).unwrap();
+    encoder.finish().unwrap();
+    adpcm
+}
+
+/// Decode the stream, passing it to the decoder `piece_size` bytes at a time.
+fn decode(adpcm: &[u8], num_channels: usize, format: ADPCMBlockFormat, layout: ADPCMBlockLayout, piece_size: usize) -> Vec<Vec<i16>> {
+    let mut sink = PlanarSink(vec![Vec::new(); num_channels]);
+    let mut decoder = XboxADPCMDecoder::with_format(num_channels, format, &mut sink);
+    decoder.set_layout(layout);
+    for piece in adpcm.chunks(piece_size) {
+        decoder.decode(piece).unwrap();
+    }
+    decoder.finish().unwrap();
+    sink.0
+}
+
+#[test]
+fn decoding_in_pieces() {
+    for &(num_channels, format) in &[(2, ADPCMBlockFormat::XBOX), (3, ADPCMBlockFormat::XBOX), (2, ADPCMBlockFormat::ima(512, 2).unwrap())] {
+        let pcm = pcm(num_channels, 3000);
+        let expected = decode(&encode(&pcm, format, ADPCMBlockLayout::Interleaved), num_channels, format, ADPCMBlockLayout::Interleaved, usize::MAX);
+
+        for layout in LAYOUTS {
+            let adpcm = encode(&pcm, format, layout);
+            for piece_size in [1, 7, 36, 100, 1000, usize::MAX] {
+                assert_eq!(decode(&adpcm, num_channels, format, layout, piece_size), expected, "{:?} in {} byte pieces", layout, piece_size);
+            }
+        }
+    }
+}
+
+#[test]
+fn resuming_from_state() {
+    let num_channels = 2;
+    let format = ADPCMBlockFormat::XBOX;
+    let pcm = pcm(num_channels, 2000);
+
+    for layout in LAYOUTS {
+        let adpcm = encode(&pcm, format, layout);
+        let expected = decode(&adpcm, num_channels, format, layout, usize::MAX);
+
+        for split in (0..adpcm.len()).step_by(13) {
+            let mut sink = PlanarSink(vec![Vec::new(); num_channels]);
+            let mut decoder = XboxADPCMDecoder::with_format(num_channels, format, &mut sink);
+            decoder.set_layout(layout);
+            decoder.decode(&adpcm[..split]).unwrap();
+            let state = decoder.state();
+            assert_eq!(state.bytes_decoded(), split);
+
+            let mut resumed_sink = PlanarSink(vec![Vec::new(); num_channels]);
+            let mut decoder = XboxADPCMDecoder::from_state(&state, &mut resumed_sink).unwrap();
+            decoder.decode(&adpcm[state.bytes_decoded()..]).unwrap();
+            decoder.finish().unwrap();
+
+            let output: Vec<Vec<i16>> = sink.0.iter().zip(resumed_sink.0.iter()).map(|(a, b)| [&a[..], &b[..]].concat()).collect();
+            assert_eq!(output, expected, "{:?} split at byte {}", layout, split);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Checks that looped encodes have block-aligned loop points and decode continuously across the wraparound.
+
+extern crate xbadpcm;
+
+use xbadpcm::{ADPCMBlockFormat, XboxADPCMDecoder, XboxADPCMDecodeSink, XboxADPCMEncoder, XboxADPCMLoopingDecoder};
+
+/// A sine wave with a period of 50 samples, so loops of a multiple of 50 samples are continuous.
+fn pcm(length: usize) -> Vec<i16> {
+    (0..length).map(|i| ((i as f64 * std::f64::consts::PI / 25.0).sin() * 8000.0) as i16).collect()
+}
+
+struct MonoSink(Vec<i16>);
+
+impl XboxADPCMDecodeSink for MonoSink {
+    type Error = ();
+
+    fn write(&mut self, samples: &[[i16; 64]]) -> Result<(), ()> {
+        self.0.extend_from_slice(&samples[0]);
+        Ok(())
+    }
+
+    fn write_partial(&mut self, samples: &[[i16; 64]], samples_amount: usize) -> Result<(), ()> {
+        self.0.extend_from_slice(&samples[0][..samples_amount]);
+        Ok(())
+    }
+}
+
+/// Get the largest difference between two samples in a row.
+fn largest_step(samples: &[i16]) -> u16 {
+    samples.windows(2).map(|w| w[0].abs_diff(w[1])).max().unwrap()
+}
+
+#[test]
+fn loop_points_are_block_aligned() {
+    let pcm = pcm(5000);
+    for &format in &[ADPCMBlockFormat::XBOX, ADPCMBlockFormat::ima(256, 1).unwrap()] {
+        let samples_per_block = format.samples_per_block();
+        for &(loop_start, loop_end) in &[(100, 900), (0, 50), (1, 4001), (1234, 4334)] {
+            let mut adpcm = Vec::new();
+            let mut encoder = XboxADPCMEncoder::with_format(1, 0, format, &mut adpcm);
+            let encoded_loop = encoder.encode_looped([&pcm[..]], loop_start, loop_end).unwrap().unwrap();
+
+            let loop_length = encoded_loop.loop_end - encoded_loop.loop_start;
+            assert_eq!(encoded_loop.loop_start, encoded_loop.loop_start_block * samples_per_block);
+            assert_eq!(encoded_loop.loop_end, encoded_loop.loop_end_block * samples_per_block);
+            assert_eq!(loop_length, (loop_end - loop_start) * encoded_loop.loop_repetitions);
+            assert_eq!(adpcm.len(), encoded_loop.loop_end_block * format.block_size(1));
+
+            // Each repetition of the loop decodes close to the input loop.
+            let mut decoded = MonoSink(Vec::new());
+            let mut decoder = XboxADPCMDecoder::with_format(1, format, &mut decoded);
+            decoder.decode(&adpcm).unwrap();
+            decoder.finish().unwrap();
+            assert_eq!(decoded.0.len(), encoded_loop.loop_end);
+
+            let input_loop = pcm[loop_start..loop_end].iter().cycle();
+            for (i, (&d, &p)) in decoded.0[encoded_loop.loop_start..].iter().zip(input_loop).enumerate() {
+                assert!(d.abs_diff(p) < 1000, "{:?} {}..{} sample {} decoded to {} instead of {}", format, loop_start, loop_end, i, d, p);
+            }
+        }
+    }
+}
+
+#[test]
+fn wraparound_is_continuous() {
+    let pcm = pcm(3000);
+    let mut adpcm = Vec::new();
+    let mut encoder = XboxADPCMEncoder::new(1, 3, &mut adpcm);
+    let encoded_loop = encoder.encode_looped([&pcm[..]], 130, 2930).unwrap().unwrap();
+
+    // Play through the loop end and jump back three times.
+    let played: Vec<i16> = XboxADPCMLoopingDecoder::new(&adpcm, 1, encoded_loop.loop_start, encoded_loop.loop_end, Some(3)).map(|f| f[0]).collect();
+    let loop_length = encoded_loop.loop_end - encoded_loop.loop_start;
+    assert_eq!(played.len(), encoded_loop.loop_end + loop_length * 3);
+
+    // Every pass through the loop is the same, and jumping back is no bigger a step than any within the loop.
+    let first_pass = &played[encoded_loop.loop_start..encoded_loop.loop_end];
+    for pass in played[encoded_loop.loop_end..].chunks(loop_length) {
+        assert_eq!(pass, first_pass);
+    }
+    for wrap in (1..=3).map(|n| encoded_loop.loop_end - 1 + (n - 1) * loop_length) {
+        assert!(played[wrap].abs_diff(played[wrap + 1]) <= largest_step(first_pass), "step of {} at {}", played[wrap].abs_diff(played[wrap + 1]), wrap);
+    }
+}
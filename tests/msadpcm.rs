@@ -0,0 +1,68 @@
+//! Checks that Microsoft ADPCM handles full-scale and corrupt input.
+
+extern crate xbadpcm;
+
+use xbadpcm::{MSADPCMDecoder, MSADPCMEncoder};
+
+/// Generate pseudo-random bytes.
+fn noise(length: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed;
+    (0..length).map(|_| {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        (state >> 24) as u8
+    }).collect()
+}
+
+#[test]
+fn corrupt_input_decodes() {
+    for seed in 0..20 {
+        let adpcm = noise(4096, seed);
+
+        let mut mono = [Vec::new()];
+        let mut decoder = MSADPCMDecoder::new(1, 256, &mut mono);
+        decoder.decode(&adpcm).unwrap();
+        decoder.finish().unwrap();
+        assert_eq!(mono[0].len(), 4096 / 256 * 500);
+
+        let mut stereo = [Vec::new(), Vec::new()];
+        let mut decoder = MSADPCMDecoder::new(2, 1024, &mut stereo);
+        decoder.decode(&adpcm).unwrap();
+        decoder.finish().unwrap();
+        assert_eq!(stereo[0].len(), 4096 / 1024 * 1012);
+    }
+}
+
+#[test]
+fn largest_steps_decode() {
+    // A header with the largest step size, followed by the largest 4-bit samples, which keep growing the step size.
+    let mut adpcm = vec![0u8, 0xFF, 0x7F, 0, 0, 0, 0];
+    adpcm.resize(2048, 0x88);
+
+    let mut output = [Vec::new()];
+    let mut decoder = MSADPCMDecoder::new(1, 2048, &mut output);
+    decoder.decode(&adpcm).unwrap();
+    decoder.finish().unwrap();
+
+    assert_eq!(output[0].len(), 2 + (2048 - 7) * 2);
+    assert!(output[0][2..].iter().all(|&s| s == i16::MIN || s == i16::MAX));
+}
+
+#[test]
+fn full_scale_square_wave_round_trips() {
+    let samples: Vec<i16> = (0..5000).map(|i| if i % 2 == 0 { i16::MAX } else { i16::MIN }).collect();
+    let mut adpcm = Vec::new();
+
+    let mut encoder = MSADPCMEncoder::new(1, 256, &mut adpcm);
+    encoder.encode([&samples]).unwrap();
+    encoder.finish().unwrap();
+
+    let mut output = [Vec::new()];
+    let mut decoder = MSADPCMDecoder::new(1, 256, &mut output);
+    decoder.decode(&adpcm).unwrap();
+    decoder.finish().unwrap();
+
+    // Each block starts with the two samples in its header.
+    for (block, decoded) in samples.chunks(500).zip(output[0].chunks(500)) {
+        assert_eq!(block[..2], decoded[..2]);
+    }
+}
@@ -0,0 +1,109 @@
+//! Checks resuming encoders from edited saved states, which must be rejected if they could not have come from an encoder
+//! and must be caught by verification if they decode differently.
+
+#![cfg(all(feature = "serde", feature = "std"))]
+
+extern crate serde_json;
+extern crate xbadpcm;
+
+use serde_json::{json, Value};
+use xbadpcm::{VerifyError, XboxADPCMEncodeSink, XboxADPCMEncoder, XboxADPCMEncoderState};
+
+struct VerifiedOutput(Vec<u8>);
+
+impl XboxADPCMEncodeSink for VerifiedOutput {
+    type Error = VerifyError;
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), VerifyError> {
+        self.0.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Get the state of an encoder partway through its second block.
+fn saved_state() -> Value {
+    let samples: Vec<i16> = (0..100).map(|i| ((i as f64 / 10.0).sin() * 8000.0) as i16).collect();
+    let mut output = Vec::new();
+    let mut encoder = XboxADPCMEncoder::new(2, 3, &mut output);
+    encoder.encode([&samples, &samples]).unwrap();
+    serde_json::to_value(encoder.state()).unwrap()
+}
+
+fn resumes(state: &Value) -> bool {
+    let state: XboxADPCMEncoderState = serde_json::from_value(state.clone()).unwrap();
+    let mut output = Vec::new();
+    XboxADPCMEncoder::from_state(&state, &mut output).is_some()
+}
+
+#[test]
+fn saved_state_resumes() {
+    let state = saved_state();
+    assert!(resumes(&state));
+
+    // The block layout is saved whether or not it is supported by the build.
+    assert_eq!(state["layout"], json!("Interleaved"));
+    assert_eq!(state["layout_buffer"], json!([]));
+}
+
+#[test]
+fn inconsistent_states_are_rejected() {
+    let cases: [(&str, Value); 12] = [
+        ("buffer_size", json!(0)),
+        ("buffer_size", json!(60)),
+        ("block_samples_encoded", json!(7)),
+        ("block_samples_encoded", json!(16)),
+        ("block_samples_encoded", Value::Null),
+        ("buffer_position", json!(0)),
+        ("samples_received", json!(101)),
+        ("output_size", json!(4)),
+        ("bytes_written", json!(0)),
+        ("blocks_encoded", json!(usize::MAX)),
+        ("predictors_initialized", json!(false)),
+        ("layout", json!({ "Stride": 0 }))
+    ];
+
+    for (field, value) in cases {
+        let mut state = saved_state();
+        state[field] = value.clone();
+        assert!(!resumes(&state), "{} = {} was accepted", field, value);
+    }
+}
+
+#[test]
+fn started_block_needs_buffered_sample() {
+    // Finishing this state would have to read the last sample before the start of the buffer.
+    let mut state = saved_state();
+    state["block_samples_encoded"] = json!(8);
+    state["buffer_size"] = json!(0);
+    assert!(!resumes(&state));
+}
+
+#[test]
+fn layout_bytes_must_match_layout() {
+    let mut state = saved_state();
+    state["layout_buffer"] = json!([0, 0, 0, 0]);
+    assert!(!resumes(&state));
+
+    // A group of 2 blocks would have been written already.
+    state["layout"] = json!({ "Stride": 1 });
+    state["layout_buffer"] = json!(vec![0; 72 * 2]);
+    assert!(!resumes(&state));
+}
+
+#[test]
+fn diverging_decoder_is_reported() {
+    // The saved decoder state of channel 1 no longer matches what the encoder reconstructed, so the next chunk of that
+    // channel decodes differently. The state is 32 samples into block 1.
+    let mut state = saved_state();
+    let pcmdata = state["verify_channels"][1]["pcmdata"].as_i64().unwrap();
+    state["verify_channels"][1]["pcmdata"] = json!(pcmdata + 100);
+    let state: XboxADPCMEncoderState = serde_json::from_value(state).unwrap();
+
+    let mut output = VerifiedOutput(Vec::new());
+    let mut encoder = XboxADPCMEncoder::from_state(&state, &mut output).unwrap();
+    encoder.set_verify(true);
+    let error = encoder.encode([[0i16; 64], [0i16; 64]]).unwrap_err();
+
+    assert_eq!((error.block, error.channel, error.sample), (1, 1, 33));
+    assert_ne!(error.decoded_sample, error.expected_sample);
+}
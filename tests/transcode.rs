@@ -0,0 +1,63 @@
+//! Checks that IMA ADPCM input of either framing transcodes to Xbox ADPCM that decodes like the input.
+
+extern crate xbadpcm;
+
+use xbadpcm::{ADPCMBlockFormat, ADPCMFraming, IMAToXboxADPCMTranscoder, XboxADPCMDecoder, XboxADPCMEncoder};
+
+fn pcm(length: usize) -> Vec<i16> {
+    (0..length).map(|i| ((i as f64 / 10.0).sin() * 8000.0 + (i as f64 / 3.0).cos() * 2000.0) as i16).collect()
+}
+
+fn encode(samples: &[i16], format: ADPCMBlockFormat) -> Vec<u8> {
+    let mut adpcm = Vec::new();
+    let mut encoder = XboxADPCMEncoder::with_format(2, 3, format, &mut adpcm);
+    encoder.encode([samples, samples]).unwrap();
+    encoder.finish().unwrap();
+    adpcm
+}
+
+fn decode(adpcm: &[u8], format: ADPCMBlockFormat) -> [Vec<i16>; 2] {
+    let mut decoded = [Vec::new(), Vec::new()];
+    let mut decoder = XboxADPCMDecoder::with_format(2, format, &mut decoded);
+    decoder.decode(adpcm).unwrap();
+    decoder.finish().unwrap();
+    decoded
+}
+
+fn transcode(adpcm: &[u8], format: ADPCMBlockFormat) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut transcoder = IMAToXboxADPCMTranscoder::new(2, format, 3, &mut output);
+    transcoder.transcode(adpcm).unwrap();
+    transcoder.finish().unwrap();
+    output
+}
+
+#[test]
+fn transcodes_each_framing() {
+    let samples = pcm(5000);
+    for &framing in &[ADPCMFraming::Overlapped, ADPCMFraming::HeaderSample] {
+        for &block_align in &[72, 256, 2048] {
+            let format = ADPCMBlockFormat::ima(block_align, 2).unwrap().with_framing(framing);
+            let input = encode(&samples, format);
+            let output = transcode(&input, format);
+
+            // The output decodes to the same samples as the input, apart from the first sample of a header sample
+            // stream, which is only stored in the first output header.
+            let skip = format.outputs_header_sample() as usize;
+            let input_decoded = decode(&input, format);
+            let output_decoded = decode(&output, ADPCMBlockFormat::XBOX);
+            for (i, o) in input_decoded.iter().zip(output_decoded.iter()) {
+                let i = &i[skip..];
+                assert_eq!(o.len(), i.len().next_multiple_of(64), "{:?} {}", framing, block_align);
+                let max_error = i.iter().zip(o.iter()).map(|(&a, &b)| a.abs_diff(b)).max().unwrap();
+                assert!(max_error < 1000, "{:?} {}: error of {}", framing, block_align, max_error);
+            }
+        }
+    }
+}
+
+#[test]
+fn xbox_input_is_copied() {
+    let input = encode(&pcm(5000), ADPCMBlockFormat::XBOX);
+    assert_eq!(transcode(&input, ADPCMBlockFormat::XBOX), input);
+}
@@ -0,0 +1,76 @@
+#!/usr/bin/env python3
+# Generates the conformance vectors in this directory.
+#
+# Expected PCM is decoded with Python's audioop module, which implements the Intel/DVI IMA ADPCM reference decoder.
+# Each block is decoded starting from the state in its header, with the header sample output first, and each byte's
+# nibbles are swapped because audioop decodes the upper four bits first.
+#
+# Requires Python 3.12 or older (audioop was removed in 3.13). The output is deterministic.
+
+import os
+import random
+import struct
+import warnings
+
+with warnings.catch_warnings():
+    warnings.simplefilter("ignore", DeprecationWarning)
+    import audioop
+
+DIRECTORY = os.path.dirname(os.path.abspath(__file__))
+
+
+def swap_nibbles(data):
+    return bytes(((b & 0xF) << 4) | (b >> 4) for b in data)
+
+
+def decode(data, num_channels, channel_block_size):
+    block_size = channel_block_size * num_channels
+    channels = [[] for _ in range(num_channels)]
+
+    for b in range(0, len(data), block_size):
+        block = data[b:b + block_size]
+        words = [block[i:i + 4] for i in range(num_channels * 4, block_size, 4)]
+
+        for c in range(num_channels):
+            sample, index = struct.unpack_from("<hB", block, c * 4)
+            nibbles = swap_nibbles(b"".join(words[c::num_channels]))
+            decoded, _ = audioop.adpcm2lin(nibbles, 2, (sample, index))
+            channels[c].append(sample)
+            channels[c].extend(struct.unpack("<%dh" % (len(decoded) // 2), decoded))
+
+    return b"".join(struct.pack("<%dh" % num_channels, *s) for s in zip(*channels))
+
+
+def block(rng, num_channels, channel_block_size, headers=None, nibbles=None):
+    data = bytearray()
+    for c in range(num_channels):
+        sample, index = headers[c] if headers else (rng.randint(-32768, 32767), rng.randint(0, 88))
+        data += struct.pack("<hBB", sample, index, 0)
+    for _ in range((channel_block_size - 4) * num_channels):
+        data.append(nibbles(rng) if nibbles else rng.randint(0, 255))
+    return bytes(data)
+
+
+def write(name, num_channels, channel_block_size, data):
+    with open(os.path.join(DIRECTORY, name + ".adpcm"), "wb") as f:
+        f.write(data)
+    with open(os.path.join(DIRECTORY, name + ".pcm"), "wb") as f:
+        f.write(decode(data, num_channels, channel_block_size))
+
+
+rng = random.Random(0x58424144)
+
+# Random data in Xbox ADPCM blocks
+write("mono_36", 1, 36, b"".join(block(rng, 1, 36) for _ in range(8)))
+write("stereo_36", 2, 36, b"".join(block(rng, 2, 36) for _ in range(8)))
+write("6ch_36", 6, 36, b"".join(block(rng, 6, 36) for _ in range(4)))
+
+# Sample clamping at both ends, and step index clamping at 88 and 0
+write("clamp_36", 2, 36,
+      block(rng, 2, 36, [(32000, 60), (-32000, 60)], lambda r: 0x77) +
+      block(rng, 2, 36, [(-32768, 88), (32767, 88)], lambda r: r.choice([0x77, 0xFF, 0x7F])) +
+      block(rng, 2, 36, [(0, 0), (100, 1)], lambda r: r.choice([0x00, 0x88, 0x08, 0x80])))
+
+# Standard IMA ADPCM block sizes
+write("mono_256", 1, 256, b"".join(block(rng, 1, 256) for _ in range(3)))
+write("stereo_1024", 2, 1024, b"".join(block(rng, 2, 1024) for _ in range(2)))
@@ -0,0 +1,64 @@
+//! Round trips looped entries through a written wave bank.
+
+extern crate xbadpcm;
+
+use xbadpcm::{WaveBank, WaveBankBuilder, XboxADPCMEncoder, XboxADPCMLoopingDecoder};
+
+fn pcm(length: usize) -> Vec<i16> {
+    (0..length).map(|i| ((i as f64 / 10.0).sin() * 8000.0) as i16).collect()
+}
+
+#[test]
+fn looped_pcm_keeps_encoded_loop() {
+    let samples = pcm(1000);
+    let mut builder = WaveBankBuilder::new("music");
+    builder.add_pcm(Some("theme"), 44100, [&samples, &samples], 3, Some((100, 900)));
+    let bank_data = builder.to_bytes();
+
+    // The entry has the same loop as encoding the input directly.
+    let mut adpcm = Vec::new();
+    let mut encoder = XboxADPCMEncoder::new(2, 3, &mut adpcm);
+    let encoded_loop = encoder.encode_looped([&samples, &samples], 100, 900).unwrap().unwrap();
+
+    let bank = WaveBank::parse(&bank_data).unwrap();
+    let entry = bank.entry(0).unwrap();
+    assert_eq!(entry.data, &adpcm[..]);
+    assert_eq!(entry.duration as usize, encoded_loop.loop_end);
+    assert_eq!(entry.loop_samples(), Some((encoded_loop.loop_start, encoded_loop.loop_end)));
+    assert_eq!(entry.loop_region.offset as usize, encoded_loop.loop_start_block * 72);
+    assert_eq!(entry.loop_region.length as usize, (encoded_loop.loop_end_block - encoded_loop.loop_start_block) * 72);
+
+    // The loop is not widened past the repeated input loop.
+    assert_eq!(encoded_loop.loop_end - encoded_loop.loop_start, 800 * encoded_loop.loop_repetitions);
+
+    let (loop_start, loop_end) = entry.loop_samples().unwrap();
+    assert_eq!(XboxADPCMLoopingDecoder::new(entry.data, 2, loop_start, loop_end, Some(1)).count(), loop_end * 2 - loop_start);
+}
+
+#[test]
+fn aligned_adpcm_loop_is_kept() {
+    let mut adpcm = Vec::new();
+    let mut encoder = XboxADPCMEncoder::new(1, 3, &mut adpcm);
+    encoder.encode([&pcm(640)]).unwrap();
+    encoder.finish().unwrap();
+
+    let mut builder = WaveBankBuilder::new("sfx");
+    builder.add_adpcm(None, 22050, 1, &adpcm, Some((128, 576)));
+    let bank_data = builder.to_bytes();
+
+    let entry = WaveBank::parse(&bank_data).unwrap().entry(0).unwrap();
+    assert_eq!(entry.loop_samples(), Some((128, 576)));
+    assert_eq!(entry.loop_region.offset, 2 * 36);
+    assert_eq!(entry.loop_region.length, 7 * 36);
+}
+
+#[test]
+#[should_panic(expected = "loop points must be at block boundaries")]
+fn unaligned_adpcm_loop_is_rejected() {
+    let mut adpcm = Vec::new();
+    let mut encoder = XboxADPCMEncoder::new(1, 3, &mut adpcm);
+    encoder.encode([&pcm(1000)]).unwrap();
+    encoder.finish().unwrap();
+
+    WaveBankBuilder::new("sfx").add_adpcm(None, 22050, 1, &adpcm, Some((100, 900)));
+}